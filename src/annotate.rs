@@ -0,0 +1,191 @@
+use topaz_tak::board::{Board5, Board6, Board7};
+use topaz_tak::{Color, GameMove, TakBoard, TakGame};
+
+use super::graph::clamp;
+use super::play::PlyEval;
+
+/// Losing at least this much compared to the engine's move is a mistake
+const MISTAKE: i32 = 100;
+/// Losing at least this much compared to the engine's move is a blunder
+const BLUNDER: i32 = 300;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Annotation {
+    pub mark: &'static str,
+    pub comment: Option<String>,
+}
+
+/// Where the game starts, so move numbers match the original ptn
+#[derive(Debug, Clone, Copy)]
+pub struct StartPosition {
+    pub move_num: usize,
+    pub color: Color,
+}
+
+impl StartPosition {
+    pub fn of(game: &TakGame) -> Self {
+        let move_num = match game {
+            TakGame::Standard5(board) => board.move_num(),
+            TakGame::Standard6(board) => board.move_num(),
+            TakGame::Standard7(board) => board.move_num(),
+            _ => 1,
+        };
+        Self {
+            move_num,
+            color: game.side_to_move(),
+        }
+    }
+    /// Move number and color of a ply counted from this position, like 12W
+    pub fn label(&self, ply: usize) -> String {
+        let offset = match self.color {
+            Color::White => 0,
+            Color::Black => 1,
+        };
+        let ply = ply + offset;
+        let color = if ply % 2 == 0 { "W" } else { "B" };
+        format!("{}{}", self.move_num + ply / 2, color)
+    }
+}
+
+pub fn ptn_moves(game: &TakGame, moves: &[GameMove]) -> Vec<String> {
+    match game {
+        TakGame::Standard5(_) => moves.iter().map(|m| m.to_ptn::<Board5>()).collect(),
+        TakGame::Standard7(_) => moves.iter().map(|m| m.to_ptn::<Board7>()).collect(),
+        _ => moves.iter().map(|m| m.to_ptn::<Board6>()).collect(),
+    }
+}
+
+fn strip_marks(ptn: &str) -> &str {
+    ptn.trim_end_matches(|c| "'\"!?*".contains(c))
+}
+
+/// Compares each played move with the engine's choice in the position before it
+pub fn annotate(evals: &[PlyEval], played: &[String], first_color: Color) -> Vec<Annotation> {
+    let eval_at = |ply: usize| evals.iter().find(|e| e.ply == ply);
+    let mut notes: Vec<Annotation> = Vec::with_capacity(played.len());
+    for (ply, mv) in played.iter().enumerate() {
+        let mut note = Annotation::default();
+        if let (Some(before), Some(after)) = (eval_at(ply), eval_at(ply + 1)) {
+            let mover = if ply % 2 == 0 {
+                first_color
+            } else {
+                !first_color
+            };
+            let orient = |score: i32| match mover {
+                Color::White => clamp(score),
+                Color::Black => -clamp(score),
+            };
+            let loss = orient(before.score) - orient(after.score);
+            let best = before.line.pv.first().map(|x| strip_marks(x));
+            let found_best = best == Some(strip_marks(mv));
+            let punished = matches!(notes.last(), Some(prev) if prev.mark.starts_with('?'));
+            if let (false, Some(best)) = (found_best, best) {
+                if loss >= MISTAKE {
+                    note.mark = if loss >= BLUNDER { "??" } else { "?" };
+                    note.comment = Some(format!("Best was {}, {} worse", best, loss));
+                }
+            } else if found_best && punished {
+                note.mark = "!";
+                note.comment = Some(String::from("Punishes the mistake"));
+            }
+        }
+        notes.push(note);
+    }
+    notes
+}
+
+/// Writes the moves with their marks and comments below the original tags
+pub fn annotated_ptn(
+    tags: &str,
+    played: &[String],
+    notes: &[Annotation],
+    start: &StartPosition,
+) -> String {
+    let moves = move_text(played, notes, start);
+    match tags.trim() {
+        "" => moves,
+        tags => format!("{}\n\n{}", tags, moves),
+    }
+}
+
+/// Writes numbered moves with their marks and comments, one move number per line
+pub fn move_text(played: &[String], notes: &[Annotation], start: &StartPosition) -> String {
+    let mut ptn = String::new();
+    let mut move_num = start.move_num;
+    let mut color = start.color;
+    if let Color::Black = color {
+        ptn.push_str(&format!("{}. --", move_num));
+    }
+    for (mv, note) in played.iter().zip(notes.iter()) {
+        if let Color::White = color {
+            ptn.push_str(&format!("{}.", move_num));
+        }
+        ptn.push_str(&format!(" {}{}", strip_marks(mv), note.mark));
+        if let Some(ref comment) = note.comment {
+            ptn.push_str(&format!(" {{{}}}", comment));
+        }
+        if let Color::Black = color {
+            ptn.push('\n');
+            move_num += 1;
+        }
+        color = !color;
+    }
+    ptn.push('\n');
+    ptn
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::play::EngineLine;
+
+    fn eval(ply: usize, score: i32, best: &str) -> PlyEval {
+        PlyEval {
+            ply,
+            score,
+            line: EngineLine {
+                pv: vec![best.to_string()],
+                ..EngineLine::default()
+            },
+        }
+    }
+
+    #[test]
+    fn marks_mistakes() {
+        let evals = vec![
+            eval(0, 0, "a1"),
+            eval(1, 10, "f6"),
+            eval(2, 0, "c3"),
+            eval(3, -400, "d4"),
+            eval(4, 20, "e5"),
+        ];
+        let played: Vec<_> = ["a1", "f6", "c4", "d4'"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        let notes = annotate(&evals, &played, Color::White);
+        assert_eq!(notes[0].mark, "");
+        assert_eq!(notes[1].mark, "");
+        assert_eq!(notes[2].mark, "??");
+        assert_eq!(notes[2].comment.as_deref(), Some("Best was c3, 400 worse"));
+        assert_eq!(notes[3].mark, "!");
+
+        let start = StartPosition {
+            move_num: 1,
+            color: Color::White,
+        };
+        let ptn = annotated_ptn("[Size \"6\"]", &played, &notes, &start);
+        assert_eq!(
+            ptn,
+            "[Size \"6\"]\n\n1. a1 f6\n2. c4?? {Best was c3, 400 worse} d4! {Punishes the mistake}\n\n"
+        );
+        let ptn = annotated_ptn("", &played, &notes, &start);
+        assert!(ptn.starts_with("1. a1 f6\n"));
+        assert_eq!(start.label(2), "2W");
+        let start = StartPosition {
+            move_num: 7,
+            color: Color::Black,
+        };
+        assert_eq!(start.label(2), "8B");
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::Write;
+
+use anyhow::{anyhow, Result};
+use rand::seq::SliceRandom;
+use topaz_tak::{GameMove, TakBoard, TakGame};
+
+use super::{parse_game, GameRecord};
+
+/// Positions seen in fewer games than this are left out of a built book
+const MIN_GAMES: u32 = 2;
+
+pub struct BookMove {
+    pub ptn: String,
+    pub weight: u32,
+}
+
+/// Weighted opening moves keyed by the tps of the position they are played from.
+///
+/// On disk each line is `tps;move;weight`, the same layout as the puzzle csv.
+#[derive(Default)]
+pub struct Book {
+    positions: HashMap<String, Vec<BookMove>>,
+}
+
+impl Book {
+    pub fn load(path: &str) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        Self::parse(&data)
+    }
+    pub fn parse(data: &str) -> Result<Self> {
+        let mut book = Self::default();
+        for (idx, line) in data.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let split: Vec<_> = line.split(';').collect();
+            if split.len() != 3 {
+                anyhow::bail!("Bad opening book line {}: {}", idx + 1, line);
+            }
+            let weight = split[2]
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("Bad weight on opening book line {}", idx + 1))?;
+            book.positions
+                .entry(split[0].to_string())
+                .or_default()
+                .push(BookMove {
+                    ptn: split[1].to_string(),
+                    weight,
+                });
+        }
+        Ok(book)
+    }
+    pub fn num_positions(&self) -> usize {
+        self.positions.len()
+    }
+    /// Picks a weighted random book move for the position, if it is in the book
+    pub fn choose<B: TakBoard + Debug>(&self, board: &B) -> Option<GameMove> {
+        let moves = self.positions.get(&format!("{:?}", board))?;
+        let legal: Vec<_> = moves
+            .iter()
+            .filter_map(|m| {
+                let mv = GameMove::try_from_ptn(&m.ptn, board)?;
+                board.legal_move(mv).then(|| (mv, m.weight))
+            })
+            .collect();
+        let mut rng = rand::thread_rng();
+        legal.choose_weighted(&mut rng, |x| x.1).ok().map(|x| x.0)
+    }
+    fn add_game<B: TakBoard + Debug>(&mut self, mut board: B, moves: &[GameMove], plies: usize) {
+        for mv in moves.iter().take(plies) {
+            let tps = format!("{:?}", board);
+            let ptn = mv.to_ptn::<B>();
+            let entry = self.positions.entry(tps).or_default();
+            if let Some(existing) = entry.iter_mut().find(|x| x.ptn == ptn) {
+                existing.weight += 1;
+            } else {
+                entry.push(BookMove { ptn, weight: 1 });
+            }
+            board.do_move(*mv);
+        }
+    }
+    /// Builds a book from the first `plies` moves of every game in a ptn archive
+    pub fn from_archive(archive: &str, plies: usize) -> Self {
+        let mut book = Self::default();
+        for game in split_games(archive) {
+            match parse_game(game) {
+                Some(GameRecord {
+                    game: TakGame::Standard5(board),
+                    moves,
+                    ..
+                }) => book.add_game(board, &moves, plies),
+                Some(GameRecord {
+                    game: TakGame::Standard6(board),
+                    moves,
+                    ..
+                }) => book.add_game(board, &moves, plies),
+                Some(GameRecord {
+                    game: TakGame::Standard7(board),
+                    moves,
+                    ..
+                }) => book.add_game(board, &moves, plies),
+                _ => tracing::warn!("Skipping unreadable game in archive"),
+            }
+        }
+        for moves in book.positions.values_mut() {
+            moves.retain(|x| x.weight >= MIN_GAMES);
+        }
+        book.positions.retain(|_, moves| !moves.is_empty());
+        book
+    }
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut positions: Vec<_> = self.positions.iter().collect();
+        positions.sort_by(|a, b| a.0.cmp(b.0));
+        for (tps, moves) in positions {
+            for mv in moves {
+                writeln!(writer, "{};{};{}", tps, mv.ptn, mv.weight)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits a file of concatenated ptn games, each starting with its tag block
+fn split_games(archive: &str) -> Vec<&str> {
+    let mut starts = Vec::new();
+    let mut in_tags = false;
+    let mut offset = 0;
+    for line in archive.split_inclusive('\n') {
+        let is_tag = line.trim_start().starts_with('[');
+        if is_tag && !in_tags {
+            starts.push(offset);
+        }
+        if !line.trim().is_empty() {
+            in_tags = is_tag;
+        }
+        offset += line.len();
+    }
+    starts.push(archive.len());
+    starts.windows(2).map(|w| &archive[w[0]..w[1]]).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ARCHIVE: &str = "[Size \"5\"]\n[Player1 \"a\"]\n\n1. a1 e5 2. c3 c2\n\n\
+        [Size \"5\"]\n[Player1 \"b\"]\n\n1. a1 e5 2. b2 c2\n\n\
+        [Size \"5\"]\n\n1. a1 e1 2. c3\n";
+
+    #[test]
+    fn split_archive() {
+        let games = split_games(ARCHIVE);
+        assert_eq!(games.len(), 3);
+        assert!(games[1].contains("b2"));
+    }
+
+    #[test]
+    fn build_book() {
+        let book = Book::from_archive(ARCHIVE, 4);
+        let mut out = Vec::new();
+        book.write(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let reread = Book::parse(&text).unwrap();
+        // a1 was played three times and e5 twice, everything else is too rare
+        assert_eq!(reread.num_positions(), 2);
+        assert!(text.contains(";a1;3"));
+        assert!(text.contains(";e5;2"));
+        let board = topaz_tak::board::Board5::new();
+        let mv = reread.choose(&board).unwrap();
+        assert_eq!(mv.to_ptn::<topaz_tak::board::Board5>(), "a1");
+    }
+}
@@ -0,0 +1,293 @@
+/// Where a command may be used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channels {
+    Any,
+    /// Only the configured puzzle channel
+    Puzzle,
+    /// Only a thread with a game against Topaz
+    GameThread,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandKind {
+    Tinue,
+    Analyze,
+    Eval,
+    Convert,
+    Cancel,
+    Puzzle,
+    Solve,
+    Play,
+    Undo,
+    Resign,
+    Version,
+    Status,
+    Ping,
+    Help,
+}
+
+pub struct Command {
+    pub kind: CommandKind,
+    /// Name without the leading `!`, may be two words like `topaz play`
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub args: &'static str,
+    pub min_args: usize,
+    pub channels: Channels,
+    pub description: &'static str,
+}
+
+impl Command {
+    pub fn usage(&self) -> String {
+        if self.args.is_empty() {
+            format!("!{}", self.name)
+        } else {
+            format!("!{} {}", self.name, self.args)
+        }
+    }
+    /// Whether the command reads a game, which may come as an attached `.ptn` file
+    pub fn takes_game(&self) -> bool {
+        matches!(
+            self.kind,
+            CommandKind::Tinue | CommandKind::Analyze | CommandKind::Eval | CommandKind::Convert
+        )
+    }
+    fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self.aliases.iter().any(|x| x.eq_ignore_ascii_case(name))
+    }
+}
+
+pub static COMMANDS: &[Command] = &[
+    Command {
+        kind: CommandKind::Tinue,
+        name: "tinue",
+        aliases: &[],
+        args: "<playtak id | ptn.ninja link | tps | ptn>",
+        min_args: 1,
+        channels: Channels::Any,
+        description: "Checks a position for tinue, or every position of a longer game.",
+    },
+    Command {
+        kind: CommandKind::Analyze,
+        name: "analyze",
+        aliases: &["analyse"],
+        args: "<playtak id | ptn.ninja link | ptn>",
+        min_args: 1,
+        channels: Channels::Any,
+        description: "Charts the engine evaluation over a whole game and marks mistakes.",
+    },
+    Command {
+        kind: CommandKind::Eval,
+        name: "eval",
+        aliases: &["evaluate"],
+        args: "<tps | ptn.ninja link | playtak id> [--multipv N]",
+        min_args: 1,
+        channels: Channels::Any,
+        description: "Gives the engine score and best line for the final position.",
+    },
+    Command {
+        kind: CommandKind::Convert,
+        name: "convert",
+        aliases: &[],
+        args: "<ptn.ninja link | playtak id | ptn | tps | [size] playtak moves>",
+        min_args: 1,
+        channels: Channels::Any,
+        description: "Turns links, games and server moves into ptn, ptn into its final tps, and a tps into a link.",
+    },
+    Command {
+        kind: CommandKind::Cancel,
+        name: "cancel",
+        aliases: &["stop"],
+        args: "",
+        min_args: 0,
+        channels: Channels::Any,
+        description: "Stops your running or queued searches. Reacting ❌ to a request also works.",
+    },
+    Command {
+        kind: CommandKind::Puzzle,
+        name: "puzzle",
+        aliases: &[],
+        args: "<easy | medium | hard | insane | id>",
+        min_args: 1,
+        channels: Channels::Puzzle,
+        description: "Starts a tinue puzzle. Reply with bare ptn moves to solve it.",
+    },
+    Command {
+        kind: CommandKind::Solve,
+        name: "solve",
+        aliases: &[],
+        args: "<legal | pv | undo | tps | link>",
+        min_args: 1,
+        channels: Channels::Puzzle,
+        description: "Helps with your active puzzle.",
+    },
+    Command {
+        kind: CommandKind::Play,
+        name: "topaz play",
+        aliases: &[],
+        args: "[5 | 6] [white | black] [komi N] [level beginner | casual | intermediate | strong | full]",
+        min_args: 0,
+        channels: Channels::Any,
+        description: "Starts a game against Topaz in a new thread.",
+    },
+    Command {
+        kind: CommandKind::Undo,
+        name: "topaz undo",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        channels: Channels::GameThread,
+        description: "Takes back your last move in a game against Topaz.",
+    },
+    Command {
+        kind: CommandKind::Resign,
+        name: "topaz resign",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        channels: Channels::GameThread,
+        description: "Resigns your game against Topaz.",
+    },
+    Command {
+        kind: CommandKind::Version,
+        name: "topaz version",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        channels: Channels::Any,
+        description: "Shows which topaz-tak revision the bot runs.",
+    },
+    Command {
+        kind: CommandKind::Status,
+        name: "topaz status",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        channels: Channels::Any,
+        description: "Shows uptime, request counts and search statistics.",
+    },
+    Command {
+        kind: CommandKind::Ping,
+        name: "ping",
+        aliases: &[],
+        args: "",
+        min_args: 0,
+        channels: Channels::Any,
+        description: "Checks that the bot is alive.",
+    },
+    Command {
+        kind: CommandKind::Help,
+        name: "help",
+        aliases: &["commands"],
+        args: "[command]",
+        min_args: 0,
+        channels: Channels::Any,
+        description: "Lists the commands, or describes one of them.",
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    let name = name.trim().trim_start_matches('!');
+    COMMANDS.iter().find(|c| c.matches(name))
+}
+
+/// A command found in a message, along with everything typed after its name
+pub struct Invocation<'a> {
+    pub command: &'static Command,
+    pub rest: &'a str,
+    pub args: Vec<&'a str>,
+}
+
+impl<'a> Invocation<'a> {
+    fn new(command: &'static Command, rest: &'a str) -> Self {
+        let rest = rest.trim();
+        Self {
+            command,
+            rest,
+            args: rest.split_whitespace().collect(),
+        }
+    }
+}
+
+fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    s.split_once(char::is_whitespace).unwrap_or((s, ""))
+}
+
+/// Finds which command a message invokes, if any
+pub fn parse(content: &str) -> Option<Invocation<'_>> {
+    let body = content.trim_start().strip_prefix('!')?;
+    let (first, after_first) = split_word(body);
+    let (second, after_second) = split_word(after_first);
+    if !second.is_empty() {
+        if let Some(command) = find(&format!("{} {}", first, second)) {
+            return Some(Invocation::new(command, after_second));
+        }
+    }
+    let command = find(first)?;
+    Some(Invocation::new(command, after_first))
+}
+
+/// Builds the `!help` reply, listing every command or describing just one
+pub fn help(name: Option<&str>) -> String {
+    if let Some(name) = name {
+        let command = match find(name) {
+            Some(command) => command,
+            None => return format!("Unknown command {}. Try !help", name),
+        };
+        let mut text = format!("`{}`\n{}", command.usage(), command.description);
+        if !command.aliases.is_empty() {
+            let aliases: Vec<_> = command.aliases.iter().map(|x| format!("!{}", x)).collect();
+            text.push_str(&format!("\nAliases: {}", aliases.join(", ")));
+        }
+        match command.channels {
+            Channels::Any => {}
+            Channels::Puzzle => text.push_str("\nOnly works in the puzzle channel."),
+            Channels::GameThread => text.push_str("\nOnly works in a game thread."),
+        }
+        text
+    } else {
+        let lines: Vec<_> = COMMANDS
+            .iter()
+            .map(|c| format!("`!{}` {}", c.name, c.description))
+            .collect();
+        format!("{}\nUse !help <command> for details.", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        let inv = parse("!tinue 12345").unwrap();
+        assert_eq!(inv.command.kind, CommandKind::Tinue);
+        assert_eq!(inv.rest, "12345");
+
+        let inv = parse("!topaz play 6 black komi 2").unwrap();
+        assert_eq!(inv.command.kind, CommandKind::Play);
+        assert_eq!(inv.args, vec!["6", "black", "komi", "2"]);
+
+        let inv = parse("!Analyse [Size \"6\"]\n\n1. a1 f6").unwrap();
+        assert_eq!(inv.command.kind, CommandKind::Analyze);
+        assert_eq!(inv.rest, "[Size \"6\"]\n\n1. a1 f6");
+
+        assert!(parse("!topaz").is_none());
+        assert!(parse("!tinuefoo").is_none());
+        assert!(parse("a1").is_none());
+        assert!(find("eval").unwrap().takes_game());
+        assert!(!find("puzzle").unwrap().takes_game());
+    }
+
+    #[test]
+    fn help_text() {
+        let all = help(None);
+        assert_eq!(all.lines().count(), COMMANDS.len() + 1);
+        let one = help(Some("!solve"));
+        assert!(one.starts_with("`!solve <legal | pv | undo | tps | link>`"));
+        assert!(one.ends_with("puzzle channel."));
+        assert!(help(Some("analyse")).contains("Aliases: !analyse"));
+    }
+}
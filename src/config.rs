@@ -0,0 +1,336 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+/// Read when `TOPAZ_CONFIG` does not name another file
+pub const DEFAULT_PATH: &str = "topaz.toml";
+
+/// Environment variables read before the config file existed, with the key that replaced each
+const LEGACY_ENV: &[(&str, &str)] = &[
+    ("CARGO_FILE", "paths.cargo_file"),
+    ("OPENING_BOOK", "paths.opening_book"),
+    ("GAME_ARCHIVE", "paths.game_archive"),
+    ("PUZZLE_CHANNEL", "puzzle_channel under [[guild]]"),
+];
+
+/// Settings read from the toml config file, see `topaz.example.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub discord: DiscordConfig,
+    #[serde(default)]
+    pub search: SearchConfig,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub paths: PathConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub games: GamesConfig,
+    #[serde(default, rename = "guild")]
+    pub guilds: Vec<GuildConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordConfig {
+    /// Falls back to `DISCORD_TOKEN` so the token can stay out of the file
+    pub token: Option<String>,
+    /// Needed for the `!` commands, slash commands work without it
+    pub message_content: bool,
+}
+
+impl Default for DiscordConfig {
+    fn default() -> Self {
+        Self {
+            token: None,
+            message_content: true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchConfig {
+    /// Node limit for each tinue search
+    pub tinue_nodes: usize,
+    pub eval_depth: usize,
+    pub eval_time_ms: u64,
+    /// Whole game analysis searches every ply, so each one gets much less time
+    pub analyze_depth: usize,
+    pub analyze_ply_ms: u64,
+    pub max_multi_pv: usize,
+    /// Wall clock limit for a queued search once it starts running. Searches only
+    /// check it between steps, so a single tinue attempt can run past it. The tinue
+    /// attempts together search less than 7/3 of `tinue_nodes`.
+    pub job_timeout_ms: u64,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            tinue_nodes: 100_000,
+            eval_depth: 20,
+            eval_time_ms: 20_000,
+            analyze_depth: 8,
+            analyze_ply_ms: 1_000,
+            max_multi_pv: 5,
+            job_timeout_ms: 300_000,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: String::from("warn"),
+        }
+    }
+}
+
+impl SearchConfig {
+    pub fn job_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.job_timeout_ms)
+    }
+}
+
+impl LogConfig {
+    pub fn level(&self) -> tracing::Level {
+        tracing::Level::from_str(&self.level).unwrap_or(tracing::Level::WARN)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathConfig {
+    /// Cargo.toml of this bot, used to report the topaz-tak revision
+    pub cargo_file: Option<String>,
+    pub puzzles: String,
+    pub opening_book: Option<String>,
+    pub game_archive: String,
+}
+
+impl Default for PathConfig {
+    fn default() -> Self {
+        Self {
+            cargo_file: None,
+            puzzles: String::from("tinue_data.csv"),
+            opening_book: None,
+            game_archive: String::from("games"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address for the `/metrics` and `/status` endpoint, off when unset
+    pub listen: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GamesConfig {
+    /// Playtak game server, games are fetched from `{base_url}/games/{id}/view`
+    pub base_url: String,
+    /// Where fetched games are kept, off when unset
+    pub cache_dir: Option<String>,
+    /// Limit for each attempt at fetching a game
+    pub timeout_ms: u64,
+    /// Attempts after the first one fails with a network or server error
+    pub retries: usize,
+}
+
+impl Default for GamesConfig {
+    fn default() -> Self {
+        Self {
+            base_url: String::from("https://playtak.com"),
+            cache_dir: None,
+            timeout_ms: 10_000,
+            retries: 2,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GuildConfig {
+    pub id: u64,
+    pub puzzle_channel: Option<u64>,
+}
+
+impl Config {
+    /// Reads the config file, using the defaults when there is none
+    pub fn load(path: &str) -> Result<Self> {
+        let data = match std::fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                eprintln!("No config file at {}, using the defaults", path);
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(anyhow!("Unable to read config file {}: {}", path, e)),
+        };
+        Self::parse(&data).map_err(|e| anyhow!("{}: {}", path, e))
+    }
+    pub fn parse(data: &str) -> Result<Self> {
+        let config: Self = toml::from_str(data)?;
+        config.validate()?;
+        Ok(config)
+    }
+    /// Reports every problem at once rather than stopping at the first
+    fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        let search = &self.search;
+        let limits = [
+            ("tinue_nodes", search.tinue_nodes as u64),
+            ("eval_depth", search.eval_depth as u64),
+            ("eval_time_ms", search.eval_time_ms),
+            ("analyze_depth", search.analyze_depth as u64),
+            ("analyze_ply_ms", search.analyze_ply_ms),
+            ("max_multi_pv", search.max_multi_pv as u64),
+            ("job_timeout_ms", search.job_timeout_ms),
+        ];
+        for (name, value) in limits {
+            if value == 0 {
+                errors.push(format!("search.{} must be greater than 0", name));
+            }
+        }
+        if tracing::Level::from_str(&self.log.level).is_err() {
+            errors.push(format!(
+                "log.level {:?} is not one of trace, debug, info, warn or error",
+                self.log.level
+            ));
+        }
+        if let Some(ref listen) = self.metrics.listen {
+            if listen.parse::<std::net::SocketAddr>().is_err() {
+                errors.push(format!(
+                    "metrics.listen {:?} is not an address like 127.0.0.1:9184",
+                    listen
+                ));
+            }
+        }
+        if self.games.timeout_ms == 0 {
+            errors.push(String::from("games.timeout_ms must be greater than 0"));
+        }
+        let base_url = &self.games.base_url;
+        if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+            errors.push(format!(
+                "games.base_url {:?} must start with http:// or https://",
+                base_url
+            ));
+        }
+        let mut seen = HashSet::new();
+        for guild in self.guilds.iter() {
+            if !seen.insert(guild.id) {
+                errors.push(format!("guild {} is configured more than once", guild.id));
+            }
+            if guild.id == 0 {
+                errors.push(String::from("guild.id must not be 0"));
+            }
+            if guild.puzzle_channel == Some(0) {
+                errors.push(format!("guild {} has a puzzle_channel of 0", guild.id));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid config:\n  {}", errors.join("\n  ")))
+        }
+    }
+    /// Checks that the named files under `[paths]` exist, for the ones a subcommand reads
+    pub fn check_paths(&self, names: &[&str]) -> Result<()> {
+        let paths = [
+            ("cargo_file", self.paths.cargo_file.as_deref()),
+            ("puzzles", Some(self.paths.puzzles.as_str())),
+            ("opening_book", self.paths.opening_book.as_deref()),
+        ];
+        let missing: Vec<_> = paths
+            .iter()
+            .filter(|(name, _)| names.contains(name))
+            .filter_map(|(name, path)| Some((name, (*path)?)))
+            .filter(|(_, path)| !Path::new(path).is_file())
+            .map(|(name, path)| format!("paths.{} {} does not exist", name, path))
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid config:\n  {}", missing.join("\n  ")))
+        }
+    }
+    /// Warnings for old environment variables that are set but no longer read
+    pub fn legacy_env_warnings() -> Vec<String> {
+        LEGACY_ENV
+            .iter()
+            .filter(|(var, _)| std::env::var_os(var).is_some())
+            .map(|(var, key)| format!("{} is no longer read, set {} in the config", var, key))
+            .collect()
+    }
+    pub fn token(&self) -> Option<String> {
+        self.discord
+            .token
+            .clone()
+            .or_else(|| std::env::var("DISCORD_TOKEN").ok())
+    }
+    pub fn puzzle_channel(&self, guild: Option<u64>) -> Option<u64> {
+        let guild = guild?;
+        self.guilds
+            .iter()
+            .find(|g| g.id == guild)
+            .and_then(|g| g.puzzle_channel)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config = Config::parse(
+            "[search]\ntinue_nodes = 5000\n\n[log]\nlevel = \"debug\"\n\n\
+            [[guild]]\nid = 12\npuzzle_channel = 34\n",
+        )
+        .unwrap();
+        assert_eq!(config.search.tinue_nodes, 5_000);
+        assert_eq!(config.search.max_multi_pv, 5);
+        assert_eq!(config.log.level(), tracing::Level::DEBUG);
+        assert!(config.discord.message_content);
+        assert_eq!(config.puzzle_channel(Some(12)), Some(34));
+        assert_eq!(config.puzzle_channel(Some(56)), None);
+    }
+
+    #[test]
+    fn invalid_config() {
+        let err = Config::parse("[search]\neval_depth = 0\n\n[log]\nlevel = \"loud\"\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("search.eval_depth must be greater than 0"));
+        assert!(err.contains("log.level \"loud\""));
+        let err = Config::parse("[games]\nbase_url = \"playtak.com\"\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("games.base_url"));
+        assert!(Config::parse("[serach]\n").is_err());
+    }
+
+    #[test]
+    fn missing_files() {
+        let config = Config::load("does/not/exist.toml").unwrap();
+        assert_eq!(config.search.tinue_nodes, 100_000);
+        let err = config.check_paths(&["puzzles"]).unwrap_err().to_string();
+        assert!(err.contains("paths.puzzles tinue_data.csv does not exist"));
+        assert!(config.check_paths(&["cargo_file", "opening_book"]).is_ok());
+        assert!(config.check_paths(&[]).is_ok());
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::{bail, Result};
+use topaz_tak::{Color, TakGame};
+
+use super::annotate::{self, Annotation, StartPosition};
+use super::playtak::PlaytakMove;
+
+/// Smallest board the bot reads, used when server moves never reach a larger square
+const MIN_SIZE: usize = 5;
+
+/// Turns playtak server moves like `P A1 M A1 A2 1` into ptn, or `None` if the text
+/// is not server notation. A leading number gives the board size, otherwise it is
+/// the smallest size that fits every square the moves touch.
+pub fn server_to_ptn(text: &str) -> Result<Option<String>> {
+    let mut words: Vec<_> = text.split_whitespace().collect();
+    let given_size = match words.first().and_then(|x| x.parse::<usize>().ok()) {
+        Some(size) => {
+            words.remove(0);
+            Some(size)
+        }
+        None => None,
+    };
+    let starts: Vec<_> = words
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| matches!(**x, "P" | "M" | "p" | "m"))
+        .map(|(idx, _)| idx)
+        .collect();
+    if starts.first() != Some(&0) {
+        return Ok(None);
+    }
+    let words: Vec<_> = words.iter().map(|x| x.to_ascii_uppercase()).collect();
+    let mut moves = Vec::new();
+    let mut size = MIN_SIZE;
+    for (idx, &start) in starts.iter().enumerate() {
+        let end = starts.get(idx + 1).copied().unwrap_or(words.len());
+        let parts: Vec<&str> = words[start..end].iter().map(|x| x.as_str()).collect();
+        let mv = match PlaytakMove::parse(&parts) {
+            Some(mv) => mv,
+            None => return Ok(None),
+        };
+        let squares = match mv {
+            PlaytakMove::Place { ref square, .. } => vec![square.clone()],
+            PlaytakMove::Move {
+                ref from, ref to, ..
+            } => vec![from.clone(), to.clone()],
+        };
+        for square in squares {
+            match square_extent(&square) {
+                Some(extent) => size = size.max(extent),
+                None => return Ok(None),
+            }
+        }
+        match mv.to_ptn() {
+            Some(ptn) => moves.push(ptn),
+            None => return Ok(None),
+        }
+    }
+    let size = match given_size {
+        Some(given) if given < size => bail!(
+            "The moves reach a {}x{} board, but the size given is {}",
+            size,
+            size,
+            given
+        ),
+        Some(given) => given,
+        None => size,
+    };
+    let notes = vec![Annotation::default(); moves.len()];
+    let start = StartPosition {
+        move_num: 1,
+        color: Color::White,
+    };
+    Ok(Some(format!(
+        "[Size \"{}\"]\n\n{}",
+        size,
+        annotate::move_text(&moves, &notes, &start)
+    )))
+}
+
+/// The board size a square needs, like 6 for `F2`
+fn square_extent(square: &str) -> Option<usize> {
+    let mut chars = square.chars();
+    let col = chars.next().filter(|c| c.is_ascii_uppercase())?;
+    let row: usize = chars.as_str().parse().ok()?;
+    Some(((col as u8 - b'A') as usize + 1).max(row))
+}
+
+/// The tps of a game's current position
+pub fn tps(game: &TakGame) -> Option<String> {
+    match game {
+        TakGame::Standard5(board) => Some(format!("{:?}", board)),
+        TakGame::Standard6(board) => Some(format!("{:?}", board)),
+        TakGame::Standard7(board) => Some(format!("{:?}", board)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn server_notation() {
+        let convert = |s: &str| server_to_ptn(s).unwrap();
+        let ptn = convert("P A1 P E5 M A1 A3 1 1").unwrap();
+        assert_eq!(ptn, "[Size \"5\"]\n\n1. a1 e5\n2. 2a1+11\n");
+        let ptn = convert("p a1 P F6 C").unwrap();
+        assert!(ptn.starts_with("[Size \"6\"]"));
+        assert!(ptn.ends_with("1. a1 Cf6\n"));
+        assert!(convert("7 P A1").unwrap().starts_with("[Size \"7\"]"));
+        assert_eq!(convert("1. a1 f6"), None);
+        assert_eq!(convert("P A1 X"), None);
+        let err = server_to_ptn("5 P F6").unwrap_err().to_string();
+        assert_eq!(err, "The moves reach a 6x6 board, but the size given is 5");
+    }
+}
@@ -0,0 +1,51 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+/// A file sent along with a message, like `tinue.svg`
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    pub fn new(name: &str, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            name: name.to_string(),
+            data: data.into(),
+        }
+    }
+}
+
+/// A message sent to the bot, whatever chat it came from.
+///
+/// Ids are only compared with each other, so a frontend may number things however it likes.
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    pub user: u64,
+    pub user_name: String,
+    pub channel: u64,
+    pub guild: Option<u64>,
+    /// Lets the user cancel the request by reacting to it, where the chat supports that
+    pub message: Option<u64>,
+    pub content: String,
+    /// Files sent with the message, like a `.ptn` game
+    pub attachments: Vec<Attachment>,
+}
+
+/// Everything the bot needs from a chat to answer a [`Request`]
+#[async_trait]
+pub trait ChatFrontend: Send + Sync {
+    /// Replies to the request being handled
+    async fn reply(&self, text: &str) -> Result<()>;
+    /// Posts to a channel without replying to anything
+    async fn send(&self, channel: u64, text: &str) -> Result<()>;
+    async fn send_files(&self, channel: u64, text: &str, files: Vec<Attachment>) -> Result<()>;
+    async fn react(&self, emoji: &str) -> Result<()>;
+    /// Opens a thread off the request for a game against Topaz, returning its channel
+    async fn create_thread(&self, name: &str) -> Result<u64>;
+    /// Whether bare moves in the request's channel answer puzzles
+    fn is_puzzle_channel(&self, request: &Request) -> bool {
+        super::config().puzzle_channel(request.guild) == Some(request.channel)
+    }
+}
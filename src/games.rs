@@ -0,0 +1,212 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use hyper::body::HttpBody;
+use hyper::StatusCode;
+
+use super::HTTP_CLIENT;
+
+/// Fetches playtak games by id, keeping a copy of each finished game on disk.
+///
+/// Finished games never change, so a cached game is always served without asking the server.
+/// Games without a result, still running or not found, are fetched again every time.
+#[derive(Debug, Clone)]
+pub struct GameSource {
+    base_url: String,
+    cache_dir: Option<PathBuf>,
+    timeout: Duration,
+    retries: usize,
+}
+
+impl GameSource {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            cache_dir: None,
+            timeout: Duration::from_secs(10),
+            retries: 2,
+        }
+    }
+    pub fn from_config(config: &super::config::GamesConfig) -> Self {
+        let source = Self::new(&config.base_url)
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .retries(config.retries);
+        match config.cache_dir {
+            Some(ref dir) => source.cache_dir(dir),
+            None => source,
+        }
+    }
+    pub fn cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+    /// Limit for each attempt, not for the whole fetch
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// Attempts after the first one fails
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+    fn cache_path(&self, id: u32) -> Option<PathBuf> {
+        Some(self.cache_dir.as_ref()?.join(format!("{}.ptn", id)))
+    }
+    /// The ptn of a playtak game
+    pub async fn fetch(&self, id: u32) -> Result<String> {
+        let cache_path = self.cache_path(id);
+        if let Some(ref path) = cache_path {
+            if let Ok(ptn) = tokio::fs::read_to_string(path).await {
+                tracing::debug!("Game {} read from cache", id);
+                return Ok(ptn);
+            }
+        }
+        let ptn = self.download(id).await?;
+        if !is_finished(&ptn) {
+            tracing::debug!("Game {} has no result yet, not caching it", id);
+            return Ok(ptn);
+        }
+        if let Some(ref path) = cache_path {
+            if let Err(e) = write_cache(path, &ptn).await {
+                tracing::warn!("Unable to cache game {}: {}", id, e);
+            }
+        }
+        Ok(ptn)
+    }
+    async fn download(&self, id: u32) -> Result<String> {
+        let url = format!("{}/games/{}/view", self.base_url, id);
+        let mut attempt = 0;
+        loop {
+            let error = match tokio::time::timeout(self.timeout, get(&url)).await {
+                Ok(Ok(ptn)) => return Ok(ptn),
+                Ok(Err(Fetch::Fatal(e))) => return Err(e),
+                Ok(Err(Fetch::Retry(e))) => e,
+                Err(_) => anyhow!("Timed out fetching game {}", id),
+            };
+            if attempt >= self.retries {
+                return Err(error);
+            }
+            attempt += 1;
+            tracing::debug!("Retrying game {} after: {}", id, error);
+            tokio::time::sleep(Duration::from_millis(250 << attempt)).await;
+        }
+    }
+}
+
+/// Whether the ptn has a final `Result` tag, like `[Result "R-0"]`
+fn is_finished(ptn: &str) -> bool {
+    ptn.lines()
+        .filter_map(|line| line.trim().strip_prefix("[Result \""))
+        .filter_map(|rest| rest.split('"').next())
+        .any(|result| !result.trim().is_empty() && result.trim() != "*")
+}
+
+async fn write_cache(path: &Path, ptn: &str) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    tokio::fs::write(path, ptn).await
+}
+
+/// Whether a failed request is worth repeating
+enum Fetch {
+    Retry(anyhow::Error),
+    Fatal(anyhow::Error),
+}
+
+async fn get(url: &str) -> Result<String, Fetch> {
+    let uri = url.parse().map_err(|e| Fetch::Fatal(anyhow!("{}", e)))?;
+    let mut res = HTTP_CLIENT
+        .get(uri)
+        .await
+        .map_err(|e| Fetch::Retry(e.into()))?;
+    match res.status() {
+        StatusCode::OK => {}
+        status if status.is_server_error() => {
+            return Err(Fetch::Retry(anyhow!("{} returned {}", url, status)))
+        }
+        status => return Err(Fetch::Fatal(anyhow!("{} returned {}", url, status))),
+    }
+    let mut buffer = Vec::new();
+    while let Some(chunk) = res.body_mut().data().await {
+        buffer.extend_from_slice(&chunk.map_err(|e| Fetch::Retry(e.into()))?);
+    }
+    let ptn = String::from_utf8(buffer).map_err(|e| Fetch::Fatal(e.into()))?;
+    if ptn.trim().is_empty() {
+        return Err(Fetch::Fatal(anyhow!("{} returned an empty game", url)));
+    }
+    Ok(ptn)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const FINISHED: &str = "[Size \"6\"]\n[Result \"R-0\"]\n\n1. a1 f6";
+
+    /// Serves a game over plain http, failing the first `failures` requests
+    async fn stand_in(failures: usize, body: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await;
+                let n = counter.fetch_add(1, Ordering::Relaxed);
+                let (status, body) = if n < failures {
+                    ("500 Internal Server Error", "")
+                } else {
+                    ("200 OK", body)
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (format!("http://{}", addr), requests)
+    }
+
+    #[tokio::test]
+    async fn retries_and_cache() {
+        let (url, requests) = stand_in(1, FINISHED).await;
+        let dir = std::env::temp_dir().join(format!("topaz-games-{}", std::process::id()));
+        let source = GameSource::new(&url).retries(1).cache_dir(&dir);
+        let ptn = source.fetch(42).await.unwrap();
+        assert!(ptn.starts_with("[Size \"6\"]"));
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+        // The second fetch never reaches the server
+        assert_eq!(source.fetch(42).await.unwrap(), ptn);
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let (url, _) = stand_in(5, FINISHED).await;
+        let source = GameSource::new(&url).retries(1);
+        assert!(source.fetch(42).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unfinished_games_are_not_cached() {
+        let (url, requests) = stand_in(0, "[Size \"6\"]\n[Result \"\"]\n\n1. a1").await;
+        let dir = std::env::temp_dir().join(format!("topaz-running-{}", std::process::id()));
+        let source = GameSource::new(&url).cache_dir(&dir);
+        source.fetch(43).await.unwrap();
+        source.fetch(43).await.unwrap();
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+        assert!(!dir.join("43.ptn").exists());
+        assert!(is_finished(FINISHED));
+        assert!(!is_finished("[Result \"*\"]"));
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt::Write;
+
+use super::annotate::StartPosition;
+use super::play::PlyEval;
+
+const WIDTH: f32 = 800.0;
+const HEIGHT: f32 = 300.0;
+const MARGIN: f32 = 40.0;
+/// Scores beyond this are drawn at the edge of the chart
+const MAX_SCORE: i32 = 1_000;
+/// A change in score of at least this much between plies is marked as a swing
+const SWING: i32 = 150;
+
+/// A move after which the evaluation changed sharply
+pub struct Swing {
+    /// Index of the move that caused the swing
+    pub ply: usize,
+    pub delta: i32,
+}
+
+impl Swing {
+    /// Move number and color, like 12W, for a game starting at `start`
+    pub fn label(&self, start: &StartPosition) -> String {
+        start.label(self.ply)
+    }
+}
+
+pub fn swings(evals: &[PlyEval]) -> Vec<Swing> {
+    evals
+        .windows(2)
+        .filter_map(|w| {
+            let delta = clamp(w[1].score) - clamp(w[0].score);
+            if delta.abs() >= SWING {
+                Some(Swing {
+                    ply: w[0].ply,
+                    delta,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Limits a score to the range shown on the chart
+pub fn clamp(score: i32) -> i32 {
+    score.clamp(-MAX_SCORE, MAX_SCORE)
+}
+
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Draws the evaluation over a game as an svg line chart, white advantage upwards
+pub fn eval_chart(title: &str, evals: &[PlyEval], start: &StartPosition) -> String {
+    let plot_w = WIDTH - 2.0 * MARGIN;
+    let plot_h = HEIGHT - 2.0 * MARGIN;
+    let last_ply = evals.last().map_or(1, |e| e.ply.max(1)) as f32;
+    let x = |ply: usize| MARGIN + ply as f32 / last_ply * plot_w;
+    let y =
+        |score: i32| MARGIN + plot_h / 2.0 - clamp(score) as f32 / MAX_SCORE as f32 * plot_h / 2.0;
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="12">"#,
+        w = WIDTH,
+        h = HEIGHT
+    );
+    let _ = writeln!(
+        svg,
+        r##"<rect width="100%" height="100%" fill="#2f3136"/><text x="{}" y="24" fill="#ffffff" text-anchor="middle" font-size="16">{}</text>"##,
+        WIDTH / 2.0,
+        escape(title)
+    );
+    // White's half of the chart is lighter, with a zero line between the halves
+    let _ = writeln!(
+        svg,
+        r##"<rect x="{m}" y="{m}" width="{pw}" height="{ph}" fill="#b9bbbe"/><rect x="{m}" y="{mid}" width="{pw}" height="{ph}" fill="#40444b"/>"##,
+        m = MARGIN,
+        pw = plot_w,
+        ph = plot_h / 2.0,
+        mid = y(0)
+    );
+    let _ = writeln!(
+        svg,
+        r##"<text x="4" y="{}" fill="#ffffff">+{}</text><text x="4" y="{}" fill="#ffffff">-{}</text>"##,
+        MARGIN + 4.0,
+        MAX_SCORE,
+        HEIGHT - MARGIN,
+        MAX_SCORE
+    );
+    let points: Vec<_> = evals
+        .iter()
+        .map(|e| format!("{:.1},{:.1}", x(e.ply), y(e.score)))
+        .collect();
+    let _ = writeln!(
+        svg,
+        r##"<polyline points="{}" fill="none" stroke="#faa61a" stroke-width="2"/>"##,
+        points.join(" ")
+    );
+    for e in evals.iter() {
+        let _ = writeln!(
+            svg,
+            r##"<circle cx="{:.1}" cy="{:.1}" r="3" fill="#faa61a"><title>Ply {}: {} ({})</title></circle>"##,
+            x(e.ply),
+            y(e.score),
+            e.ply,
+            e.score,
+            escape(&e.line.pv.join(" "))
+        );
+    }
+    for swing in swings(evals) {
+        let after = swing.ply + 1;
+        let score = evals.iter().find(|e| e.ply == after).map_or(0, |e| e.score);
+        let _ = writeln!(
+            svg,
+            r##"<circle cx="{:.1}" cy="{:.1}" r="7" fill="none" stroke="#ed4245" stroke-width="2"><title>{} swings {:+}</title></circle>"##,
+            x(after),
+            y(score),
+            swing.label(start),
+            swing.delta
+        );
+    }
+    let _ = writeln!(
+        svg,
+        r##"<text x="{}" y="{}" fill="#ffffff" text-anchor="middle">Ply</text></svg>"##,
+        WIDTH / 2.0,
+        HEIGHT - 12.0
+    );
+    svg
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::play::EngineLine;
+    use topaz_tak::Color;
+
+    fn evals(scores: &[i32]) -> Vec<PlyEval> {
+        scores
+            .iter()
+            .enumerate()
+            .map(|(ply, &score)| PlyEval {
+                ply,
+                score,
+                line: EngineLine::default(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_swings() {
+        let evals = evals(&[0, 20, -10, 400, 380, 5_000]);
+        let swings = swings(&evals);
+        assert_eq!(swings.len(), 2);
+        let start = StartPosition {
+            move_num: 1,
+            color: Color::White,
+        };
+        assert_eq!(swings[0].label(&start), "2W");
+        let start = StartPosition {
+            move_num: 7,
+            color: Color::Black,
+        };
+        assert_eq!(swings[0].label(&start), "8B");
+        assert_eq!(swings[0].delta, 410);
+        // Won positions are clamped so they don't dwarf every other swing
+        assert_eq!(swings[1].delta, MAX_SCORE - 380);
+    }
+
+    #[test]
+    fn chart() {
+        let start = StartPosition {
+            move_num: 1,
+            color: Color::White,
+        };
+        let svg = eval_chart("Alice <3 vs Bob", &evals(&[0, 20, -10, 400]), &start);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("Alice &lt;3 vs Bob"));
+        assert_eq!(svg.matches("stroke=\"#ed4245\"").count(), 1);
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{anyhow, bail, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::frontend::Attachment;
+
+lazy_static! {
+    static ref CODE_BLOCK: Regex =
+        Regex::new(r"(?s)```(?:[a-zA-Z]+\n)?(.*?)```|`([^`]+)`").unwrap();
+    static ref PLAYTAK_URL: Regex = Regex::new(r"playtak\.com/games/(\d+)").unwrap();
+    static ref TPS: Regex =
+        Regex::new(r"^([0-9xSC,]+(?:/[0-9xSC,]+)+)\s+([12])\s+(\d+)\s*(.*)$").unwrap();
+    static ref KOMI: Regex = Regex::new(r"(?i)komi\s*[=:]?\s*(-?\d+(?:\.\d+)?)").unwrap();
+    static ref SIZE: Regex = Regex::new(r"(?i)size\s*[=:]?\s*(\d)|\b(\d)x(\d)\b").unwrap();
+}
+
+/// Where a game comes from once the input form is known
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Playtak(u32),
+    /// The part of a ptn.ninja link after the domain
+    Ninja(String),
+    Ptn(String),
+}
+
+/// A game given to a command, and how it was given
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameInput {
+    pub source: Source,
+    /// Told back to the user, like "a playtak link"
+    pub form: String,
+}
+
+impl GameInput {
+    fn new(source: Source, form: &str) -> Self {
+        Self {
+            source,
+            form: form.to_string(),
+        }
+    }
+    /// Works out which form the game was given in.
+    ///
+    /// An attached `.ptn` file wins when the message has no other text.
+    pub fn detect(details: &str, attachments: &[Attachment]) -> Result<Self> {
+        let details = details.trim();
+        if details.is_empty() {
+            if let Some(file) = attachments.iter().find(|f| f.name.ends_with(".ptn")) {
+                let ptn = String::from_utf8(file.data.clone())
+                    .map_err(|_| anyhow!("{} is not a text file", file.name))?;
+                let form = format!("attached file {}", file.name);
+                return Ok(Self::new(Source::Ptn(ptn), &form));
+            }
+        }
+        if let Some(cap) = CODE_BLOCK.captures(details) {
+            let inner = cap.get(1).or_else(|| cap.get(2)).map_or("", |x| x.as_str());
+            let mut input = Self::detect(inner, &[])?;
+            input.form = format!("{} in a code block", input.form);
+            return Ok(input);
+        }
+        if let Ok(id) = details.parse::<u32>() {
+            return Ok(Self::new(Source::Playtak(id), "a playtak id"));
+        }
+        if let Some(cap) = PLAYTAK_URL.captures(details) {
+            let id = cap[1].parse()?;
+            return Ok(Self::new(Source::Playtak(id), "a playtak link"));
+        }
+        if let Some(link) = details.split("ptn.ninja/").nth(1) {
+            return Ok(Self::new(
+                Source::Ninja(link.to_string()),
+                "a ptn.ninja link",
+            ));
+        }
+        if let Some(ptn) = tps_to_ptn(details)? {
+            return Ok(Self::new(Source::Ptn(ptn), "a tps"));
+        }
+        Ok(Self::new(Source::Ptn(details.to_string()), "ptn"))
+    }
+}
+
+/// Turns a tps with an optional komi or size after it into ptn tags
+fn tps_to_ptn(details: &str) -> Result<Option<String>> {
+    let cap = match TPS.captures(details) {
+        Some(cap) => cap,
+        None => return Ok(None),
+    };
+    let tps = format!("{} {} {}", &cap[1], &cap[2], &cap[3]);
+    let size = cap[1].split('/').count();
+    let rest = &cap[4];
+    if let Some(given) = SIZE.captures(rest) {
+        let given: usize = given
+            .get(1)
+            .or_else(|| given.get(2))
+            .map_or("0", |x| x.as_str())
+            .parse()?;
+        if given != size {
+            bail!("The tps has {} rows but the size given is {}", size, given);
+        }
+    }
+    let mut ptn = format!("[Size \"{}\"]\n", size);
+    if let Some(komi) = KOMI.captures(rest) {
+        if super::parse_komi(&komi[1]).is_none() {
+            bail!(
+                "Komi {} is not supported, use 0 to 3 in steps of 0.5",
+                &komi[1]
+            );
+        }
+        ptn.push_str(&format!("[Komi \"{}\"]\n", &komi[1]));
+    }
+    ptn.push_str(&format!("[TPS \"{}\"]\n\n", tps));
+    Ok(Some(ptn))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn input_forms() {
+        let detect = |s: &str| GameInput::detect(s, &[]).unwrap();
+        assert_eq!(detect(" 123 ").source, Source::Playtak(123));
+        let url = detect("https://playtak.com/games/456/ninjaviewer");
+        assert_eq!(url.source, Source::Playtak(456));
+        assert_eq!(url.form, "a playtak link");
+        let ninja = detect("https://ptn.ninja/NoBQ&name=Ao");
+        assert_eq!(ninja.source, Source::Ninja(String::from("NoBQ&name=Ao")));
+        let block = detect("```ptn\n[Size \"6\"]\n\n1. a1 f6\n```");
+        assert_eq!(
+            block.source,
+            Source::Ptn(String::from("[Size \"6\"]\n\n1. a1 f6\n"))
+        );
+        assert_eq!(block.form, "ptn in a code block");
+        assert_eq!(detect("`789`").form, "a playtak id in a code block");
+        assert_eq!(detect("1. a1 f6").form, "ptn");
+
+        let files = [Attachment::new("game.ptn", "[Size \"5\"]")];
+        let file = GameInput::detect("", &files).unwrap();
+        assert_eq!(file.source, Source::Ptn(String::from("[Size \"5\"]")));
+        assert_eq!(file.form, "attached file game.ptn");
+    }
+
+    #[test]
+    fn tps_annotations() {
+        let tps = "x5/x5/x5/x5/1,x4 2 1";
+        let detect = |s: &str| GameInput::detect(s, &[]).unwrap().source;
+        let plain = format!("[Size \"5\"]\n[TPS \"{}\"]\n\n", tps);
+        assert_eq!(detect(tps), Source::Ptn(plain));
+        let komi = format!("[Size \"5\"]\n[Komi \"2.5\"]\n[TPS \"{}\"]\n\n", tps);
+        assert_eq!(
+            detect(&format!("{} komi 2.5", tps)),
+            Source::Ptn(komi.clone())
+        );
+        assert_eq!(detect(&format!("{} 5x5 komi=2.5", tps)), Source::Ptn(komi));
+        assert!(GameInput::detect(&format!("{} size 6", tps), &[]).is_err());
+        let err = GameInput::detect(&format!("{} komi 3.7", tps), &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Komi 3.7 is not supported, use 0 to 3 in steps of 0.5"
+        );
+        assert!(GameInput::detect(&format!("{} komi 4", tps), &[]).is_err());
+    }
+}
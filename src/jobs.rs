@@ -0,0 +1,158 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref JOBS: Mutex<Vec<JobEntry>> = Mutex::new(Vec::new());
+}
+
+/// Why a job stopped before finishing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stopped {
+    Cancelled,
+    TimedOut,
+}
+
+impl std::fmt::Display for Stopped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stopped::Cancelled => write!(f, "Cancelled."),
+            Stopped::TimedOut => write!(f, "Timed out. Sorry."),
+        }
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+/// Who asked for a job, and the message that asked for it if there was one
+#[derive(Debug, Clone, Copy)]
+pub struct Owner {
+    pub user: u64,
+    pub message: Option<u64>,
+}
+
+/// Checked by long searches between steps, since a running search can't be interrupted
+#[derive(Debug, Clone)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancelToken {
+    pub(crate) fn new() -> Self {
+        Self {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            deadline: None,
+        }
+    }
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+    pub fn stopped(&self) -> Option<Stopped> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            Some(Stopped::Cancelled)
+        } else if self.deadline.map_or(false, |d| Instant::now() >= d) {
+            Some(Stopped::TimedOut)
+        } else {
+            None
+        }
+    }
+    /// Time left before the deadline, `None` when there is none
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|d| d.saturating_duration_since(Instant::now()))
+    }
+    pub fn check(&self) -> Result<()> {
+        match self.stopped() {
+            Some(stopped) => Err(stopped.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+struct JobEntry {
+    id: u64,
+    owner: Owner,
+    token: CancelToken,
+}
+
+/// Removes a job from the list once it finishes, however it finishes
+struct JobGuard(u64);
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        JOBS.lock().unwrap().retain(|job| job.id != self.0);
+    }
+}
+
+/// Queues a search on the blocking thread where its owner can cancel it.
+///
+/// The timeout counts from when the job starts running, not from when it was queued.
+pub async fn run<F, T>(owner: Owner, timeout: Duration, f: F) -> Result<T>
+where
+    F: FnOnce(&CancelToken) -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    static NEXT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let token = CancelToken::new();
+    JOBS.lock().unwrap().push(JobEntry {
+        id,
+        owner,
+        token: token.clone(),
+    });
+    let _guard = JobGuard(id);
+    super::metrics::blocking(move || {
+        let mut token = token;
+        token.check()?;
+        token.deadline = Some(Instant::now() + timeout);
+        f(&token)
+    })
+    .await?
+}
+
+/// Cancels every running or queued job of the user, returning how many there were
+pub fn cancel_user(user: u64) -> usize {
+    let jobs = JOBS.lock().unwrap();
+    let owned: Vec<_> = jobs.iter().filter(|job| job.owner.user == user).collect();
+    for job in owned.iter() {
+        job.token.cancel();
+    }
+    owned.len()
+}
+
+/// Cancels the job started by a message, if the user reacting owns it
+pub fn cancel_message(message: u64, user: u64) -> bool {
+    let jobs = JOBS.lock().unwrap();
+    let job = jobs
+        .iter()
+        .find(|job| job.owner.message == Some(message) && job.owner.user == user);
+    if let Some(job) = job {
+        job.token.cancel();
+    }
+    job.is_some()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let token = CancelToken::new();
+        assert!(token.check().is_ok());
+        let timed = CancelToken {
+            deadline: Some(Instant::now()),
+            ..token.clone()
+        };
+        assert_eq!(timed.stopped(), Some(Stopped::TimedOut));
+        token.cancel();
+        // Cancelling wins over a timeout, and reaches every clone
+        assert_eq!(timed.stopped(), Some(Stopped::Cancelled));
+        let err = token.check().unwrap_err();
+        assert_eq!(err.downcast_ref::<Stopped>(), Some(&Stopped::Cancelled));
+    }
+}
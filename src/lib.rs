@@ -0,0 +1,1263 @@
+use anyhow::{anyhow, Result};
+use dotenv;
+use hyper::client::HttpConnector;
+use lazy_static::lazy_static;
+use lz_str::decompress_from_encoded_uri_component;
+use once_cell::sync::OnceCell;
+use puzzle::{Difficulty, TinueResponse};
+use regex::Regex;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time;
+use topaz_tak::board::{Board5, Board6, Board7};
+use topaz_tak::search::proof::TinueSearch;
+use topaz_tak::{generate_all_moves, Position};
+use topaz_tak::{Color, GameMove, TakBoard, TakGame};
+
+use hyper_rustls::HttpsConnector;
+use std::sync::{Arc, Mutex};
+
+mod annotate;
+pub mod book;
+pub mod commands;
+pub mod config;
+mod convert;
+pub mod frontend;
+pub mod games;
+mod graph;
+pub mod input;
+pub mod jobs;
+pub mod metrics;
+pub mod ninja;
+pub mod play;
+pub mod playtak;
+mod proof;
+mod puzzle;
+pub mod repl;
+
+use frontend::{Attachment, ChatFrontend, Request};
+
+lazy_static! {
+    static ref HTTP_CLIENT: hyper::Client<HttpsConnector<HttpConnector>> = {
+        let https = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+
+        let client: hyper::Client<_, hyper::Body> = hyper::Client::builder().build(https);
+        client
+    };
+    static ref ACTIVE_PUZZLES: Arc<Mutex<HashMap<u64, puzzle::PuzzleState>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+}
+
+static TOPAZ_VERSION: OnceCell<String> = OnceCell::new();
+static OPENING_BOOK: OnceCell<book::Book> = OnceCell::new();
+static CONFIG: OnceCell<config::Config> = OnceCell::new();
+static GAME_SOURCE: OnceCell<games::GameSource> = OnceCell::new();
+
+/// The loaded config, or the defaults when none was loaded, as in tests
+pub fn config() -> &'static config::Config {
+    CONFIG.get_or_init(config::Config::default)
+}
+
+fn game_source() -> &'static games::GameSource {
+    GAME_SOURCE.get_or_init(|| games::GameSource::from_config(&config().games))
+}
+
+/// Answers a message from any chat, replying through its frontend
+pub async fn handle_request(frontend: &dyn ChatFrontend, request: &Request) {
+    if let Some(invocation) = commands::parse(&request.content) {
+        let command = invocation.command;
+        if !allowed_in(frontend, command.channels, request) {
+            return;
+        }
+        // An attached game stands in for the arguments of commands that read one
+        let has_game = command.takes_game() && !request.attachments.is_empty();
+        if invocation.args.len() < command.min_args && !has_game {
+            let _ = frontend
+                .reply(&format!("Usage: `{}`", command.usage()))
+                .await;
+            return;
+        }
+        metrics::record_request(command.name);
+        if let Err(e) = run_command(frontend, request, &invocation).await {
+            match e.downcast_ref::<jobs::Stopped>() {
+                Some(jobs::Stopped::Cancelled) => {}
+                Some(stopped) => {
+                    let _ = frontend.reply(&stopped.to_string()).await;
+                }
+                None => {
+                    tracing::warn!("Error handling {} request: {}", command.name, e);
+                    react(frontend, "❌").await;
+                }
+            }
+        }
+    } else if play::is_game_channel(request.channel) {
+        if let Err(e) = play::handle_game_message(frontend, request).await {
+            tracing::warn!("Error handling game message: {}", e);
+            react(frontend, "❌").await;
+        }
+    } else if frontend.is_puzzle_channel(request) {
+        if let Some(reply) = puzzle_move(request.user, &request.content) {
+            let _ = frontend.reply(&reply).await;
+        }
+    }
+}
+
+fn allowed_in(
+    frontend: &dyn ChatFrontend,
+    channels: commands::Channels,
+    request: &Request,
+) -> bool {
+    match channels {
+        commands::Channels::Any => true,
+        commands::Channels::Puzzle => frontend.is_puzzle_channel(request),
+        commands::Channels::GameThread => play::is_game_channel(request.channel),
+    }
+}
+
+async fn run_command(
+    frontend: &dyn ChatFrontend,
+    request: &Request,
+    invocation: &commands::Invocation<'_>,
+) -> Result<()> {
+    use commands::CommandKind;
+    match invocation.command.kind {
+        CommandKind::Tinue => {
+            tracing::debug!("Running Tinue...");
+            react(frontend, "👍").await;
+            handle_tinue_req(frontend, request, invocation.rest).await
+        }
+        CommandKind::Analyze => {
+            react(frontend, "👍").await;
+            handle_analyze_req(frontend, request, invocation.rest).await
+        }
+        CommandKind::Eval => {
+            react(frontend, "👍").await;
+            handle_eval_req(frontend, request, invocation.rest).await
+        }
+        CommandKind::Convert => handle_convert_req(frontend, request, invocation.rest).await,
+        CommandKind::Puzzle => {
+            if let Some(reply) = start_puzzle(request.user, invocation.args[0]) {
+                frontend.reply(&reply).await?;
+            }
+            Ok(())
+        }
+        CommandKind::Solve => {
+            let reply = solve_command(request.user, invocation.args[0]);
+            frontend.reply(&reply).await?;
+            Ok(())
+        }
+        CommandKind::Play => play::handle_play_req(frontend, request, &invocation.args).await,
+        CommandKind::Undo | CommandKind::Resign => {
+            play::handle_game_message(frontend, request).await
+        }
+        CommandKind::Version => {
+            if let Some(version) = TOPAZ_VERSION.get() {
+                frontend.reply(version).await?;
+            } else {
+                frontend.reply("Unk").await?;
+                tracing::warn!("Unable to find topaz version, maybe Cargo location not supplied?");
+            }
+            Ok(())
+        }
+        CommandKind::Cancel => {
+            let reply = match jobs::cancel_user(request.user) {
+                0 => String::from("You have no running or queued searches."),
+                1 => String::from("Cancelled your search."),
+                n => format!("Cancelled your {} searches.", n),
+            };
+            frontend.reply(&reply).await?;
+            Ok(())
+        }
+        CommandKind::Status => {
+            let status = metrics::Snapshot::take().status_text();
+            frontend.reply(&status).await?;
+            Ok(())
+        }
+        CommandKind::Ping => {
+            tracing::debug!("Should send pong...");
+            frontend.send(request.channel, "Pong!").await?;
+            Ok(())
+        }
+        CommandKind::Help => {
+            let name = invocation.args.first().copied();
+            frontend.reply(&commands::help(name)).await?;
+            Ok(())
+        }
+    }
+}
+
+/// Starts a puzzle for the user, replying with its link
+pub fn start_puzzle(user: u64, query: &str) -> Option<String> {
+    let puzzle_data = match query.to_lowercase().as_str() {
+        "easy" => puzzle::random_puzzle(Difficulty::Easy),
+        "medium" => puzzle::random_puzzle(Difficulty::Medium),
+        "hard" => puzzle::random_puzzle(Difficulty::Hard),
+        "insane" => puzzle::random_puzzle(Difficulty::Insane),
+        _ => {
+            let id: usize = query.parse().ok().unwrap_or(0);
+            let max_puzzle = match puzzle::puzzle_length() {
+                Ok(max_puzzle) => max_puzzle,
+                Err(e) => return Some(format!("Sorry, puzzles are unavailable. {}", e)),
+            };
+            if id >= max_puzzle {
+                return Some(format!(
+                    "Please choose a puzzle between 0 and {}",
+                    max_puzzle - 1
+                ));
+            }
+            puzzle::new_puzzle(id).transpose()?
+        }
+    };
+    let puzzle_data = match puzzle_data {
+        Ok(puzzle_data) => puzzle_data,
+        Err(e) => return Some(format!("Sorry, puzzles are unavailable. {}", e)),
+    };
+    let difficulty = puzzle_data.human_difficulty();
+    let id = puzzle_data.id();
+    let link = puzzle_link(puzzle_data.build_board(), &format!("Puzzle {}", id));
+    {
+        let mut locked = ACTIVE_PUZZLES.lock().expect("Lock is not poisoned");
+        locked.insert(user, puzzle_data);
+    }
+    Some(format!(
+        "Puzzle {}\nDifficulty {}\n{}",
+        id, difficulty, link
+    ))
+}
+
+/// Answers a !solve helper command for the user's active puzzle
+pub fn solve_command(user: u64, arg: &str) -> String {
+    let ptn_str = clean_ptn_move(arg);
+    if let Some(puzzle) = ACTIVE_PUZZLES.lock().unwrap().get_mut(&user) {
+        let command = &ptn_str.to_ascii_lowercase();
+        if command == "legal" {
+            let legal_moves = puzzle.legal_moves();
+            if legal_moves.is_empty() {
+                String::from("None")
+            } else {
+                legal_moves.join(", ")
+            }
+        } else if command == "pv" {
+            let moves = puzzle.initial_pv();
+            moves.join(" ")
+        } else if command == "undo" {
+            puzzle.undo_player_move();
+            "Undo completed. Note exact / valid distinction may be lost.".to_string()
+        } else if command == "tps" {
+            let board = puzzle.build_board();
+            format!("{:?}", board)
+        } else if command == "link" {
+            let board = puzzle.build_board();
+            puzzle_link(board, "Working Solution")
+        } else {
+            String::from("Could not interpret command. To give a solution use bare ptn.")
+        }
+    } else {
+        format!("You have no active puzzles. Create one with !puzzle command")
+    }
+}
+
+/// Plays the user's move in their active puzzle, or `None` if the message is not a move
+pub fn puzzle_move(user: u64, content: &str) -> Option<String> {
+    let ptn_str = content.split_whitespace().nth(0).unwrap_or("");
+    let ptn_str = clean_ptn_move(ptn_str);
+    let mut locked = ACTIVE_PUZZLES.lock().unwrap();
+    if let Some(puzzle) = locked.get_mut(&user) {
+        if !PTN_MOVE.is_match(&ptn_str) {
+            return None;
+        }
+        if let Some(resp) = puzzle.user_play_move(&ptn_str) {
+            puzzle.apply_move(&ptn_str);
+            let mv = resp.inner();
+            if let Some(mv) = mv {
+                puzzle.apply_move(&mv.to_ptn::<Board6>());
+            }
+            if resp.is_terminal() {
+                locked.remove(&user);
+            }
+            Some(tinue_move_reply(resp))
+        } else {
+            Some(format!(
+                "Could not interpret {} as a legal ptn move",
+                ptn_str
+            ))
+        }
+    } else if PTN_MOVE.is_match(&ptn_str) {
+        Some(format!(
+            "You have no active puzzles. Create one with !puzzle command"
+        ))
+    } else {
+        None
+    }
+}
+
+/// Legal moves in the user's active puzzle, empty when they have none
+pub fn puzzle_legal_moves(user: u64) -> Vec<String> {
+    ACTIVE_PUZZLES
+        .lock()
+        .unwrap()
+        .get(&user)
+        .map(|puzzle| puzzle.legal_moves())
+        .unwrap_or_default()
+}
+
+fn clean_ptn_move(s: &str) -> String {
+    let needs_upper = s
+        .chars()
+        .take(2)
+        .filter(|&x| x.is_ascii_alphabetic())
+        .count()
+        == 2;
+    if needs_upper {
+        format!(
+            "{}{}",
+            s.chars().nth(0).unwrap().to_ascii_uppercase(),
+            s.chars()
+                .skip(1)
+                .map(|x| x.to_ascii_lowercase())
+                .collect::<String>()
+        )
+    } else {
+        s.to_ascii_lowercase()
+    }
+}
+
+fn tinue_move_reply(resp: TinueResponse) -> String {
+    match resp {
+        TinueResponse::ExactResponse(mv) => {
+            let mv = format_move(mv);
+            format!("Exact Response: {}", mv)
+        }
+        TinueResponse::ValidResponse(mv) => {
+            let mv = format_move(mv);
+            format!("Valid Response: {}", mv)
+        }
+        TinueResponse::UnclearResponse(mv) => {
+            let mv = format_move(mv);
+            format!("Unclear Response: {}", mv)
+        }
+        TinueResponse::PoorResponse(mv) => {
+            let mv = format_move(mv);
+            format!("Poor Response: {}", mv)
+        }
+        TinueResponse::Road => String::from("Road completed!"),
+        TinueResponse::NoThreats(mv) => {
+            let mv = format_move(mv);
+            format!("After {} no tak threats left. Puzzle failed.", mv)
+        }
+    }
+}
+
+fn format_move(mv: Option<GameMove>) -> String {
+    if let Some(mv) = mv {
+        mv.to_ptn::<Board6>()
+    } else {
+        String::from("_")
+    }
+}
+
+fn decompress_uri(s: &str) -> Option<String> {
+    decompress_from_encoded_uri_component(s).and_then(|x| String::from_utf16(&x).ok())
+}
+
+async fn react(frontend: &dyn ChatFrontend, unicode: &str) {
+    if let Err(_) = frontend.react(unicode).await {
+        tracing::warn!("Failed to send reaction: {}", unicode);
+    }
+}
+
+/// Sets up the state shared by every frontend from a loaded config
+/// Sets the global config and loads the files it names. `needed` are the paths the
+/// subcommand reads, as passed to [`config::Config::check_paths`].
+pub fn init(loaded: config::Config, needed: &[&str]) -> Result<()> {
+    CONFIG.set(loaded).expect("Config is only set once");
+    if let Some(ref f) = config().paths.cargo_file {
+        if let Some(version) = read_cargo_toml(f) {
+            TOPAZ_VERSION.set(version).unwrap();
+        }
+    }
+    if let Some(ref f) = config().paths.opening_book {
+        // Subcommands that don't read the book, like build-book, run without it
+        if needed.contains(&"opening_book") {
+            let book = book::Book::load(f)
+                .map_err(|e| anyhow!("Unable to load opening book {}: {}", f, e))?;
+            let _ = OPENING_BOOK.set(book);
+        }
+    }
+    Ok(())
+}
+
+pub fn init_tracing() {
+    let subscriber = tracing_subscriber::FmtSubscriber::builder()
+        // all spans/events with a level higher than TRACE (e.g, debug, info, warn, etc.)
+        // will be written to stdout.
+        .with_max_level(config().log.level())
+        .finish();
+    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+}
+
+lazy_static! {
+    static ref PTN_META: Regex = Regex::new(r#"\[(?P<Key>.*?) "(?P<Value>.*?)"\]"#).unwrap();
+    static ref PTN_MOVE: Regex = Regex::new(r#"([SCsc1-8]?[A-Ha-h]\d[+-<>]?\d*['"]*)"#).unwrap();
+    static ref VER_RE: Regex = Regex::new(r#"rev = "\S+""#).unwrap();
+    static ref MULTI_PV: Regex = Regex::new(r#"--multipv\s+(\d+)"#).unwrap();
+}
+
+/// Node budget of the first attempt at a tinue search, see [`thread_search`]
+const FIRST_TINUE_NODES: usize = 10_000;
+
+fn read_cargo_toml(s: &str) -> Option<String> {
+    let path = std::path::Path::new(s);
+    let data = std::fs::read_to_string(path).ok()?;
+    for line in data.lines() {
+        if line.starts_with("topaz-tak") {
+            if let Some(ver) = VER_RE.find(line) {
+                return Some(ver.as_str().to_string());
+            }
+        }
+    }
+    None
+}
+
+/// The job owner for a request, so the user can cancel it
+fn request_owner(request: &Request) -> jobs::Owner {
+    jobs::Owner {
+        user: request.user,
+        message: request.message,
+    }
+}
+
+async fn handle_tinue_req(
+    frontend: &dyn ChatFrontend,
+    request: &Request,
+    details: &str,
+) -> Result<()> {
+    let owner = request_owner(request);
+    let report = tinue_report(details, &request.attachments, &request.user_name, owner).await?;
+    if report.files.is_empty() {
+        frontend.send(request.channel, &report.content).await?;
+    } else {
+        frontend
+            .send_files(request.channel, &report.content, report.files)
+            .await?;
+    }
+    Ok(())
+}
+
+/// The outcome of a tinue check, shared by the text and slash commands
+pub struct TinueReport {
+    pub content: String,
+    /// The proof flamegraph and explorer, when a single position was searched
+    pub files: Vec<Attachment>,
+}
+
+pub async fn tinue_report(
+    details: &str,
+    attachments: &[Attachment],
+    name: &str,
+    owner: jobs::Owner,
+) -> Result<TinueReport> {
+    let start_time = time::Instant::now();
+    let loaded = load_game(details, attachments).await?;
+    let input = input_line(&loaded.form);
+    let GameRecord {
+        mut game,
+        mut moves,
+        info,
+    } = loaded.record;
+    if let Some(ply) = loaded.ply {
+        // Check the position the link was opened at rather than the whole game
+        for mv in moves.drain(..).take(ply) {
+            game.do_move(mv);
+        }
+    }
+    if moves.len() <= 5 {
+        // Interpret as a single position
+        let report = match game {
+            TakGame::Standard5(board) => find_one_tinue(board, owner).await?,
+            TakGame::Standard6(board) => find_one_tinue(board, owner).await?,
+            TakGame::Standard7(board) => find_one_tinue(board, owner).await?,
+            _ => anyhow::bail!("Unsupported board size"),
+        };
+        return Ok(TinueReport {
+            content: info.with_header(format!("{}\n{}", report.content, input)),
+            ..report
+        });
+    }
+    let timeout = config().search.job_timeout();
+    let tinue_plies = match game {
+        TakGame::Standard5(board) => {
+            jobs::run(owner, timeout, move |token| {
+                find_all_tinue(board, &moves, token)
+            })
+            .await?
+        }
+        TakGame::Standard6(board) => {
+            jobs::run(owner, timeout, move |token| {
+                find_all_tinue(board, &moves, token)
+            })
+            .await?
+        }
+        TakGame::Standard7(board) => {
+            jobs::run(owner, timeout, move |token| {
+                find_all_tinue(board, &moves, token)
+            })
+            .await?
+        }
+        _ => anyhow::bail!("Unsupported board size"),
+    };
+
+    let mut tinue = Vec::new();
+    let mut road = Vec::new();
+    let mut timeout = Vec::new();
+    for ply in tinue_plies.into_iter() {
+        match ply {
+            TinueStatus::Tinue(_) => tinue.push(ply.to_string()),
+            TinueStatus::Road(_) => road.push(ply.to_string()),
+            TinueStatus::Timeout(_) => timeout.push(ply.to_string()),
+        }
+    }
+    let printable = |vec: Vec<String>| {
+        if vec.len() == 0 {
+            "None".to_string()
+        } else {
+            vec.join(", ")
+        }
+    };
+    let duration = time::Instant::now().duration_since(start_time);
+    let content = format!(
+        "Sure thing, {}! Completed in {} ms.\nTinue: {}\nRoad: {}\nTimeout: {}\n{}",
+        name,
+        duration.as_millis(),
+        printable(tinue),
+        printable(road),
+        printable(timeout),
+        input,
+    );
+    Ok(TinueReport {
+        content: info.with_header(content),
+        files: Vec::new(),
+    })
+}
+
+/// Evaluates every ply of a game and replies with a chart of the evaluation
+async fn handle_analyze_req(
+    frontend: &dyn ChatFrontend,
+    request: &Request,
+    details: &str,
+) -> Result<()> {
+    let LoadedGame {
+        record, ptn, form, ..
+    } = load_game(details, &request.attachments).await?;
+    let GameRecord { game, moves, info } = record;
+    let start = annotate::StartPosition::of(&game);
+    let played = annotate::ptn_moves(&game, &moves);
+    let owner = request_owner(request);
+    let timeout = config().search.job_timeout();
+    let evals = jobs::run(owner, timeout, move |token| {
+        play::evaluate_game(game, &moves, token)
+    })
+    .await?;
+    let title = format!("Evaluation for {}", request.user_name);
+    let svg = graph::eval_chart(&title, &evals, &start);
+    let notes = annotate::annotate(&evals, &played, start.color);
+    let tags = match ptn.rsplit_once(']') {
+        Some((tags, _)) => format!("{}]", tags),
+        None => String::new(),
+    };
+    let annotated = annotate::annotated_ptn(&tags, &played, &notes, &start);
+    let mistakes: Vec<_> = notes
+        .iter()
+        .enumerate()
+        .filter(|(_, n)| n.mark.starts_with('?'))
+        .map(|(ply, n)| format!("{}{}", start.label(ply), n.mark))
+        .collect();
+    let swings: Vec<_> = graph::swings(&evals)
+        .into_iter()
+        .map(|s| format!("{} ({:+})", s.label(&start), s.delta))
+        .collect();
+    let swings = if swings.is_empty() {
+        "None".to_string()
+    } else {
+        swings.join(", ")
+    };
+    let mistakes = if mistakes.is_empty() {
+        "None".to_string()
+    } else {
+        mistakes.join(", ")
+    };
+    let final_score = evals.last().map_or(0, |e| e.score);
+    let files = vec![
+        Attachment::new("eval.svg", svg),
+        Attachment::new("annotated.ptn", annotated),
+    ];
+    let content = format!(
+        "Evaluated {} plies, final score {:+} for white.\nSwings: {}\nMistakes: {}\n{}",
+        evals.len(),
+        final_score,
+        swings,
+        mistakes,
+        input_line(&form)
+    );
+    let content = info.with_header(content);
+    frontend
+        .send_files(request.channel, &content, files)
+        .await?;
+    Ok(())
+}
+
+/// A game read from any of the input forms, with the ptn it was read from
+struct LoadedGame {
+    record: GameRecord,
+    ptn: String,
+    form: String,
+    /// Plies played in the position a ptn.ninja link was opened at
+    ply: Option<usize>,
+    source: input::Source,
+}
+
+/// Reads a game from any of the forms in [`input::GameInput`]
+async fn load_game(details: &str, attachments: &[Attachment]) -> Result<LoadedGame> {
+    let input = input::GameInput::detect(details, attachments)?;
+    let mut form = input.form.clone();
+    let (ptn, ply) = match input.source {
+        input::Source::Ninja(ref link) => {
+            let link = ninja::decode(link)?;
+            if let Some(ref name) = link.name {
+                form = format!("{} to {}", form, name);
+            }
+            let ply = link.ply.map(|ply| ply.played());
+            if let Some(ply) = ply {
+                form = format!("{} opened at ply {}", form, ply);
+            }
+            // Branches aren't read, so a link opened on one is checked on the main line
+            if let Some(ref branch) = link.target_branch {
+                form = format!(
+                    "{} on branch {}, using the main line instead since branches aren't followed",
+                    form, branch
+                );
+            }
+            (link.ptn, ply)
+        }
+        input::Source::Playtak(id) => (game_source().fetch(id).await?, None),
+        input::Source::Ptn(ref ptn) => (ptn.clone(), None),
+    };
+    let record = parse_game(&ptn).ok_or_else(|| anyhow!("Unable to parse game"))?;
+    Ok(LoadedGame {
+        record,
+        ptn,
+        form,
+        ply,
+        source: input.source,
+    })
+}
+
+/// Longest message Discord accepts, longer output is sent as a file
+const MESSAGE_LIMIT: usize = 2000;
+
+/// Converts between ptn, tps, ptn.ninja links and playtak server notation:
+/// links, playtak games and server moves become ptn, ptn with moves becomes
+/// the tps of its final position, and a bare position becomes a link
+async fn handle_convert_req(
+    frontend: &dyn ChatFrontend,
+    request: &Request,
+    details: &str,
+) -> Result<()> {
+    let server_ptn = match convert::server_to_ptn(details) {
+        Ok(ptn) => ptn,
+        Err(e) => {
+            frontend.reply(&e.to_string()).await?;
+            return Ok(());
+        }
+    };
+    let (output, form, name) = if let Some(ptn) = server_ptn {
+        parse_game(&ptn).ok_or_else(|| anyhow!("Unable to parse game"))?;
+        (
+            ptn,
+            String::from("playtak server notation"),
+            "converted.ptn",
+        )
+    } else {
+        let LoadedGame {
+            record,
+            ptn,
+            form,
+            source,
+            ..
+        } = load_game(details, &request.attachments).await?;
+        let GameRecord {
+            mut game, moves, ..
+        } = record;
+        match source {
+            input::Source::Ptn(_) if moves.is_empty() => {
+                let link = ninja::LinkBuilder::new(&game)
+                    .ok_or_else(|| anyhow!("Unsupported board size"))?
+                    .name("Converted")
+                    .build();
+                (link, form, "converted.txt")
+            }
+            input::Source::Ptn(_) => {
+                for mv in moves {
+                    game.do_move(mv);
+                }
+                let tps = convert::tps(&game).ok_or_else(|| anyhow!("Unsupported board size"))?;
+                (tps, form, "converted.tps")
+            }
+            _ => (ptn, form, "converted.ptn"),
+        }
+    };
+    let output = output.trim();
+    let shown = if name.ends_with(".ptn") {
+        format!("```\n{}\n```", output)
+    } else if name.ends_with(".tps") {
+        format!("`{}`", output)
+    } else {
+        output.to_string()
+    };
+    let content = format!("{}\n{}", shown, input_line(&form));
+    if content.len() <= MESSAGE_LIMIT {
+        frontend.reply(&content).await?;
+    } else {
+        let files = vec![Attachment::new(name, output)];
+        frontend
+            .send_files(request.channel, &input_line(&form), files)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Last line of a reply, telling the user how their input was read
+fn input_line(form: &str) -> String {
+    format!("Read as {}.", form)
+}
+
+/// Searches the final position of a game and replies with the engine's line,
+/// or with a table of the best few lines when `--multipv N` is given
+async fn handle_eval_req(
+    frontend: &dyn ChatFrontend,
+    request: &Request,
+    details: &str,
+) -> Result<()> {
+    let multi_pv = match MULTI_PV.captures(details) {
+        Some(cap) => cap[1]
+            .parse::<usize>()?
+            .clamp(1, config().search.max_multi_pv),
+        None => 1,
+    };
+    let details = MULTI_PV.replace(details, "");
+    let loaded = load_game(details.trim(), &request.attachments).await?;
+    let GameRecord {
+        mut game,
+        moves,
+        info,
+    } = loaded.record;
+    // A ptn.ninja link is evaluated at the ply it was opened at
+    for mv in moves.into_iter().take(loaded.ply.unwrap_or(usize::MAX)) {
+        game.do_move(mv);
+    }
+    let side = game.side_to_move();
+    let builder =
+        ninja::LinkBuilder::new(&game).ok_or_else(|| anyhow!("Unsupported board size"))?;
+    let white_score = |score: i32| match side {
+        Color::White => score,
+        Color::Black => -score,
+    };
+    let owner = request_owner(request);
+    let timeout = config().search.job_timeout();
+    let reply = if multi_pv > 1 {
+        let lines = jobs::run(owner, timeout, move |token| {
+            play::evaluate_multi_pv(game, multi_pv, token)
+        })
+        .await?;
+        let mut table = String::from("```\n#  Move   Score  Line\n");
+        for (idx, line) in lines.iter().enumerate() {
+            table.push_str(&format!(
+                "{:<2} {:<6} {:>+6} {}\n",
+                idx + 1,
+                line.pv.first().map_or("", |x| x.as_str()),
+                white_score(line.score),
+                line.pv.join(" ")
+            ));
+        }
+        table.push_str("```");
+        let mut builder = builder.name("Topaz lines");
+        if let Some((first, rest)) = lines.split_first() {
+            builder = builder.moves(first.pv.clone());
+            for line in rest {
+                builder = builder.variation(0, line.pv.clone());
+            }
+        }
+        format!("Scores for white\n{}\n{}", table, builder.build())
+    } else {
+        let line = jobs::run(owner, timeout, move |token| {
+            play::evaluate_position(game, token)
+        })
+        .await?;
+        let link = builder.name("Topaz PV").moves(line.pv.clone()).build();
+        format!(
+            "Score {:+} for white, depth {}, {} nodes\nPV: {}\n{}",
+            white_score(line.score),
+            line.depth,
+            line.nodes,
+            line.pv.join(" "),
+            link
+        )
+    };
+    let reply = format!("{}\n{}", reply, input_line(&loaded.form));
+    frontend.reply(&info.with_header(reply)).await?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TinueStatus {
+    Tinue(usize),
+    Road(usize),
+    Timeout(usize),
+}
+
+impl std::fmt::Display for TinueStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let ply = match self {
+            TinueStatus::Tinue(x) => x,
+            TinueStatus::Road(x) => x,
+            TinueStatus::Timeout(x) => x,
+        };
+        let color = if ply % 2 == 0 { "W" } else { "B" };
+        let move_num = ply / 2;
+        write!(f, "{}{}", move_num, color)
+    }
+}
+
+fn thread_search<T: TakBoard + std::fmt::Debug + Clone>(
+    board: T,
+    token: &jobs::CancelToken,
+) -> Result<Option<(bool, Vec<Attachment>)>> {
+    let start = time::Instant::now();
+    let tps = format!("{:?}", board);
+    let (attacker, move_num) = (board.side_to_move(), board.move_num());
+    let limit = config().search.tinue_nodes;
+    // A running search can't be interrupted, so it restarts with four times the
+    // budget until it finishes, checking for a cancel or the deadline in between.
+    // The attempts below the limit add up to less than 4/3 of the largest one, so
+    // all of them together search less than 7/3 of `tinue_nodes`, or 1.5 times
+    // with the default limit (10k + 40k + 100k). The last attempt can run past
+    // the deadline since only `tinue_nodes` bounds it.
+    let mut nodes = FIRST_TINUE_NODES.min(limit);
+    let (mut search, tinue) = loop {
+        let mut search = TinueSearch::new(board.clone()).limit(nodes).quiet();
+        let tinue = search.is_tinue();
+        if !search.aborted() || nodes >= limit || token.stopped().is_some() {
+            break (search, tinue);
+        }
+        nodes = (nodes * 4).min(limit);
+    };
+    metrics::record_tinue(start.elapsed(), search.aborted());
+    if search.aborted() {
+        token.check()?;
+        tracing::debug!("Aborting search on: {:?}", search.board);
+        // Todo
+        return Ok(None);
+    }
+    let tinue = tinue.ok_or_else(|| anyhow!("Failed to execute Tinue check"))?;
+    tracing::debug!("Valid Tinue Result Received: {:?}", search.board);
+    // Kept in memory so searches running one after another never share files
+    let mut folded = Vec::new();
+    let mut hist = Vec::new();
+    let mut zobrist_hist = std::collections::HashSet::new();
+    search.rebuild(
+        &mut folded,
+        &mut hist,
+        &mut zobrist_hist,
+        search.is_attacker(),
+    )?;
+    let folded = String::from_utf8(folded)?;
+    let proof = proof::Proof::parse(&tps, attacker, move_num, tinue, &folded);
+    let mut files = vec![Attachment::new("tinue.svg", proof.flamegraph()?)];
+    // The flamegraph is enough on its own, so an unreadable move only costs the explorer
+    match proof.explorer(&board) {
+        Ok(html) => files.push(Attachment::new("tinue.html", html)),
+        Err(e) => tracing::warn!("Unable to build proof explorer for {}: {}", tps, e),
+    }
+    Ok(Some((tinue, files)))
+}
+
+async fn find_one_tinue<T: TakBoard + std::fmt::Debug + Clone + Send + 'static>(
+    board: T,
+    owner: jobs::Owner,
+) -> Result<TinueReport> {
+    let timeout = config().search.job_timeout();
+    let tinue = jobs::run(owner, timeout, move |token| thread_search(board, token)).await?;
+    if let Some((tinue, files)) = tinue {
+        let st = if tinue {
+            "Tinue Found!"
+        } else {
+            "No Tinue Found."
+        };
+        return Ok(TinueReport {
+            content: format!(
+                "{}\n{}",
+                st, "Open these files in a web browser for best results."
+            ),
+            files,
+        });
+    }
+    Ok(TinueReport {
+        content: String::from("Timed out. Sorry."),
+        files: Vec::new(),
+    })
+}
+
+fn find_all_tinue<T: TakBoard + std::fmt::Debug>(
+    mut board: T,
+    moves: &[GameMove],
+    token: &jobs::CancelToken,
+) -> Result<Vec<TinueStatus>> {
+    let mut vec = Vec::new();
+    for (idx, mv) in moves.iter().enumerate() {
+        if idx < 6 {
+            board.do_move(*mv);
+            continue;
+        }
+        token.check()?;
+        let s = move_s(idx);
+        let start = time::Instant::now();
+        let mut search = TinueSearch::new(board)
+            .limit(config().search.tinue_nodes)
+            .quiet();
+        let result = search.is_tinue();
+        metrics::record_tinue(start.elapsed(), result.is_none());
+        if let Some(is_tinue) = result {
+            if is_tinue {
+                let road_move = find_road_move(&mut search.board);
+                if road_move.is_some() {
+                    vec.push(TinueStatus::Road(idx + 2));
+                    tracing::debug!("{}: Road", s);
+                } else {
+                    vec.push(TinueStatus::Tinue(idx + 2));
+                    tracing::debug!("{}: Tinue", s);
+                }
+            } else {
+                tracing::debug!("{}: Not Tinue", s);
+            }
+        } else {
+            vec.push(TinueStatus::Timeout(idx + 2));
+            tracing::debug!("{}: Timeout\nTimeout TPS {:?}", s, search.board);
+        }
+        board = search.board;
+        board.do_move(*mv);
+    }
+    Ok(vec)
+}
+
+fn find_road_move<B: TakBoard>(board: &mut B) -> Option<GameMove> {
+    let mut moves = Vec::new();
+    let side = board.side_to_move();
+    generate_all_moves(board, &mut moves);
+    let road = moves.into_iter().find(|mv| {
+        let rev = board.do_move(*mv);
+        let road = board.road(side);
+        board.reverse_move(rev);
+        road
+    });
+    road
+}
+
+fn move_s(idx: usize) -> String {
+    let color = if idx == 0 {
+        "B"
+    } else if idx == 1 {
+        "W"
+    } else if idx % 2 == 0 {
+        "W"
+    } else {
+        "B"
+    };
+    format!("{}. {}", idx / 2, color)
+}
+
+fn parse_move(mv_str: &str, size: usize, color: Color) -> Option<GameMove> {
+    let mut iter = mv_str.chars().take_while(|&c| c != '\'' && c != '"');
+    let first = iter.next()?;
+    let mv = if first == 'S' || first == 's' {
+        let mut s = String::new();
+        // Lowercase everything besides this first S
+        s.push(first.to_ascii_uppercase());
+        while let Some(c) = iter.next() {
+            s.push(c.to_ascii_lowercase());
+        }
+        s
+    } else if first == 'C' || first == 'c' {
+        let mut s = String::new();
+        // We need to find out if this C is a capstone or a bad square indicator
+        let second = iter.next()?;
+        if second.is_ascii_alphabetic() {
+            // The previous must have been a capstone, make it capitalized
+            s.push(first.to_ascii_uppercase());
+            s.push(second.to_ascii_lowercase());
+        } else {
+            // The second must be a number, meaning this should be lowercase c
+            s.push(first.to_ascii_lowercase());
+            s.push(second);
+        }
+        while let Some(c) = iter.next() {
+            s.push(c.to_ascii_lowercase());
+        }
+        s
+    } else {
+        let mut string = String::new();
+        string.push(first.to_ascii_lowercase());
+        for c in iter.map(|c| c.to_ascii_lowercase()) {
+            string.push(c);
+        }
+        string
+    };
+    let mv = GameMove::try_from_ptn_m(&mv, size, color)?;
+    Some(mv)
+}
+
+/// Who played a game and where, from its ptn tags
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GameInfo {
+    pub white: Option<String>,
+    pub black: Option<String>,
+    pub date: Option<String>,
+    pub result: Option<String>,
+    pub event: Option<String>,
+    pub round: Option<String>,
+}
+
+impl GameInfo {
+    fn from_tags(meta: &HashMap<String, String>) -> Self {
+        let tag = |key: &str| {
+            meta.get(key)
+                .map(|x| x.trim().to_string())
+                .filter(|x| !x.is_empty())
+        };
+        Self {
+            white: tag("Player1"),
+            black: tag("Player2"),
+            date: tag("Date"),
+            result: tag("Result"),
+            event: tag("Event"),
+            round: tag("Round"),
+        }
+    }
+    /// A line like "Alice vs Bob, 2024-05-01, R-0", or `None` without any tags
+    pub fn header(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if self.white.is_some() || self.black.is_some() {
+            parts.push(format!(
+                "{} vs {}",
+                self.white.as_deref().unwrap_or("?"),
+                self.black.as_deref().unwrap_or("?")
+            ));
+        }
+        match (&self.event, &self.round) {
+            (Some(event), Some(round)) => parts.push(format!("{} round {}", event, round)),
+            (Some(event), None) => parts.push(event.clone()),
+            (None, Some(round)) => parts.push(format!("Round {}", round)),
+            (None, None) => {}
+        }
+        parts.extend(self.date.iter().cloned());
+        parts.extend(self.result.iter().cloned());
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(", "))
+        }
+    }
+    /// Puts the header, if there is one, above a reply
+    fn with_header(&self, content: String) -> String {
+        match self.header() {
+            Some(header) => format!("{}\n{}", header, content),
+            None => content,
+        }
+    }
+}
+
+/// A parsed game, its starting position and moves along with its tags
+#[derive(Debug, Clone)]
+pub struct GameRecord {
+    pub game: TakGame,
+    pub moves: Vec<GameMove>,
+    pub info: GameInfo,
+}
+
+fn parse_game(full_ptn: &str) -> Option<GameRecord> {
+    let mut meta = HashMap::new();
+    let mut moves = Vec::new();
+    let mut color = Color::White;
+    for m in PTN_META.captures_iter(&full_ptn) {
+        meta.insert(m["Key"].to_string(), m["Value"].to_string());
+    }
+    let size = meta.get("Size")?.parse().ok()?;
+    let moves_text = full_ptn.split("]").last()?;
+    for m in PTN_MOVE.captures_iter(&moves_text) {
+        let mv = parse_move(&m[0], size, color)?;
+        moves.push(mv);
+        color = !color;
+    }
+    let komi = meta.get("Komi").and_then(|k| parse_komi(k)).unwrap_or(0);
+    if let Some(tps) = meta.get("TPS") {
+        tracing::debug!("TPS: {}", tps);
+        let game = TakGame::try_from_tps(tps).ok()?;
+        let game = match game {
+            TakGame::Standard5(b) => TakGame::Standard5(b.with_komi(komi)),
+            TakGame::Standard6(b) => TakGame::Standard6(b.with_komi(komi)),
+            TakGame::Standard7(b) => TakGame::Standard7(b.with_komi(komi)),
+            _ => return None,
+        };
+        if let Color::Black = game.side_to_move() {
+            for i in 0..moves.len() {
+                let mv = moves[i];
+                if mv.is_place_move() {
+                    moves[i] =
+                        GameMove::from_placement(mv.place_piece().swap_color(), mv.src_index());
+                }
+            }
+        }
+        return Some(GameRecord {
+            game,
+            moves,
+            info: GameInfo::from_tags(&meta),
+        });
+    }
+    Some(GameRecord {
+        game: empty_game(size, komi)?,
+        moves,
+        info: GameInfo::from_tags(&meta),
+    })
+}
+
+/// Converts a ptn komi value into the half flat units used by topaz
+fn parse_komi(komi: &str) -> Option<u8> {
+    let komi = match komi {
+        "0" => 0,
+        "0.5" => 1,
+        "1" => 2,
+        "1.5" => 3,
+        "2" => 4,
+        "2.5" => 5,
+        "3" => 6,
+        _ => return None,
+    };
+    Some(komi)
+}
+
+fn empty_game(size: usize, komi: u8) -> Option<TakGame> {
+    match size {
+        5 => Some(TakGame::Standard5(Board5::new().with_komi(komi))),
+        6 => Some(TakGame::Standard6(Board6::new().with_komi(komi))),
+        7 => Some(TakGame::Standard7(Board7::new().with_komi(komi))),
+        _ => None,
+    }
+}
+
+fn puzzle_link(board: Board6, name: &str) -> String {
+    ninja::LinkBuilder::new(&TakGame::Standard6(board))
+        .expect("6x6 links are supported")
+        .name(name)
+        .players("White", "Black")
+        .build()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn uri() {
+        let data = "NoBQNghgngpgTgRgAQCIDqALAlgFxigXQFgAoUSWOAJlQCFIBjAa0NOAGVcZUAHHAOwB0-LPwBWEVmU4AvbigBsU4ABUQ7VAA8ANAgRVtmgCwB6HfqpU9l7QZtWAwrZMH9twwGYzu9wh9V2EzcEdkMfP01vfSdNV10kGioATmUAeR4YEX4Ac1QAZwB3CB4pUmTBIA&name=OoCwlgLgpgBAbgZxgIQDYEMDGBrGA2ADzyA";
+        // let data = "NoBQNghgngpgTgRgAQCIDqALAlgFxigXQFgAoUSWOAJlQCFIBjAa0NOAGVcZUAHHAOwB0-LPwBWEVmU4AvbigBsU4ABUQ7VAA8ANAgRVtmgCwB6HfqpU9l7QZtWAwrZMH9twwGYzu9wh9V2EzcEdkMfP01vfSdNV10kGioATmUAeR4YEX4Ac1QAZwB3CB4pUmTBIA&name=AoVwXmA2CmAECMQ";
+        // let data = "NoBQNghgngpgTgRgAQCIDqALAlgFxigXQFgAoUSWOAJlQCFIBjAa0NOAGVcZUAHHAOwB0-LPwBWEVmU4AvbigBsU4ABUQ7VAA8ANAgRVtmgCwB6HfqpU9l7QZtWAwrZMH9twwGYzu9wh9V2EzcEdkMfP01vfSdNV10kGioATmUAeR4YEX4Ac1QAZwB3CB4pUmTBIA&name=OoCwlgLgpgBAbgZxgIQDYEMDGBrGA2ADzxgAoBmAJgEog";
+        let text = decompress_uri(data).unwrap();
+        println!("{}", text);
+        let compressed = lz_str::compress_to_encoded_uri_component(&text);
+        println!("{}", compressed);
+        println!("{}", decompress_uri(&compressed).unwrap());
+        println!("{}", decompress_uri("AoVwXmA2CmAECMQ").unwrap());
+    }
+
+    #[test]
+    fn game_info() {
+        let ptn = "[Player1 \"Alice\"]\n[Player2 \"Bob\"]\n[Date \"2024-05-01\"]\n\
+            [Result \"R-0\"]\n[Size \"6\"]\n\n1. a1 f6";
+        let record = parse_game(ptn).unwrap();
+        assert_eq!(record.moves.len(), 2);
+        assert_eq!(record.info.white.as_deref(), Some("Alice"));
+        assert_eq!(
+            record.info.header().unwrap(),
+            "Alice vs Bob, 2024-05-01, R-0"
+        );
+        let info = GameInfo {
+            black: Some(String::from("Bob")),
+            event: Some(String::from("Open")),
+            round: Some(String::from("2")),
+            ..GameInfo::default()
+        };
+        assert_eq!(info.header().unwrap(), "? vs Bob, Open round 2");
+        assert_eq!(GameInfo::default().header(), None);
+    }
+
+    /// Records what the bot sends instead of posting it anywhere
+    #[derive(Default)]
+    struct Recorder {
+        sent: Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl ChatFrontend for Recorder {
+        async fn reply(&self, text: &str) -> Result<()> {
+            self.sent.lock().unwrap().push(format!("reply: {}", text));
+            Ok(())
+        }
+        async fn send(&self, channel: u64, text: &str) -> Result<()> {
+            self.sent
+                .lock()
+                .unwrap()
+                .push(format!("send {}: {}", channel, text));
+            Ok(())
+        }
+        async fn send_files(&self, channel: u64, text: &str, files: Vec<Attachment>) -> Result<()> {
+            let names: Vec<_> = files.iter().map(|f| f.name.as_str()).collect();
+            self.sent.lock().unwrap().push(format!(
+                "send {}: {} [{}]",
+                channel,
+                text,
+                names.join(", ")
+            ));
+            Ok(())
+        }
+        async fn react(&self, emoji: &str) -> Result<()> {
+            self.sent.lock().unwrap().push(format!("react: {}", emoji));
+            Ok(())
+        }
+        async fn create_thread(&self, _name: &str) -> Result<u64> {
+            Ok(99)
+        }
+    }
+
+    #[tokio::test]
+    async fn requests() {
+        let frontend = Recorder::default();
+        let request = |content: &str| Request {
+            user: 1,
+            user_name: String::from("tester"),
+            channel: 2,
+            content: content.to_string(),
+            ..Request::default()
+        };
+        handle_request(&frontend, &request("!ping")).await;
+        handle_request(&frontend, &request("!help ping")).await;
+        handle_request(&frontend, &request("!cancel")).await;
+        handle_request(&frontend, &request("hello")).await;
+        let sent = frontend.sent.lock().unwrap();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0], "send 2: Pong!");
+        assert!(sent[1].starts_with("reply: `!ping`"));
+        assert_eq!(sent[2], "reply: You have no running or queued searches.");
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+lazy_static! {
+    static ref STARTED: Instant = Instant::now();
+    static ref REQUESTS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+}
+
+/// Jobs waiting for or running on the single blocking thread
+static QUEUE_DEPTH: AtomicUsize = AtomicUsize::new(0);
+static TINUE_SEARCHES: AtomicU64 = AtomicU64::new(0);
+static TINUE_MICROS: AtomicU64 = AtomicU64::new(0);
+static TINUE_TIMEOUTS: AtomicU64 = AtomicU64::new(0);
+
+/// Starts the uptime clock
+pub fn start() {
+    lazy_static::initialize(&STARTED);
+}
+
+pub fn record_request(command: &'static str) {
+    *REQUESTS.lock().unwrap().entry(command).or_default() += 1;
+}
+
+pub fn record_tinue(elapsed: Duration, timed_out: bool) {
+    TINUE_SEARCHES.fetch_add(1, Ordering::Relaxed);
+    TINUE_MICROS.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    if timed_out {
+        TINUE_TIMEOUTS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+struct QueueGuard;
+
+impl QueueGuard {
+    fn new() -> Self {
+        QUEUE_DEPTH.fetch_add(1, Ordering::Relaxed);
+        Self
+    }
+}
+
+impl Drop for QueueGuard {
+    fn drop(&mut self) {
+        QUEUE_DEPTH.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Runs a search on the blocking thread, counting it in the queue depth until it finishes
+pub async fn blocking<F, T>(f: F) -> Result<T, tokio::task::JoinError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let _guard = QueueGuard::new();
+    tokio::task::spawn_blocking(f).await
+}
+
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    pub uptime: Duration,
+    pub requests: Vec<(&'static str, u64)>,
+    pub queue_depth: usize,
+    pub tinue_searches: u64,
+    pub tinue_time: Duration,
+    pub tinue_timeouts: u64,
+    pub active_puzzles: usize,
+    pub active_games: usize,
+}
+
+impl Snapshot {
+    pub fn take() -> Self {
+        let requests = REQUESTS
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .collect();
+        Self {
+            uptime: STARTED.elapsed(),
+            requests,
+            queue_depth: QUEUE_DEPTH.load(Ordering::Relaxed),
+            tinue_searches: TINUE_SEARCHES.load(Ordering::Relaxed),
+            tinue_time: Duration::from_micros(TINUE_MICROS.load(Ordering::Relaxed)),
+            tinue_timeouts: TINUE_TIMEOUTS.load(Ordering::Relaxed),
+            active_puzzles: super::ACTIVE_PUZZLES.lock().unwrap().len(),
+            active_games: super::play::active_game_count(),
+        }
+    }
+    fn average_tinue_ms(&self) -> u128 {
+        if self.tinue_searches == 0 {
+            0
+        } else {
+            self.tinue_time.as_millis() / self.tinue_searches as u128
+        }
+    }
+    /// Human readable summary for `!topaz status`
+    pub fn status_text(&self) -> String {
+        let secs = self.uptime.as_secs();
+        let requests = if self.requests.is_empty() {
+            String::from("None")
+        } else {
+            let counts: Vec<_> = self
+                .requests
+                .iter()
+                .map(|(name, count)| format!("{} {}", name, count))
+                .collect();
+            counts.join(", ")
+        };
+        format!(
+            "Uptime {}d {}h {}m\nRequests: {}\nQueue depth {}\nTinue searches {}, average {} ms, {} timeouts\nActive puzzles {}, active games {}",
+            secs / 86_400,
+            secs / 3_600 % 24,
+            secs / 60 % 60,
+            requests,
+            self.queue_depth,
+            self.tinue_searches,
+            self.average_tinue_ms(),
+            self.tinue_timeouts,
+            self.active_puzzles,
+            self.active_games,
+        )
+    }
+    /// The same data in the Prometheus text exposition format
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{}", value);
+        };
+        let uptime = self.uptime.as_secs_f64();
+        metric(
+            "topaz_uptime_seconds",
+            "gauge",
+            "Seconds since the bot started",
+            format!("topaz_uptime_seconds {}", uptime),
+        );
+        let requests: Vec<_> = self
+            .requests
+            .iter()
+            .map(|(name, count)| format!("topaz_requests_total{{command=\"{}\"}} {}", name, count))
+            .collect();
+        metric(
+            "topaz_requests_total",
+            "counter",
+            "Commands handled, by command",
+            requests.join("\n"),
+        );
+        metric(
+            "topaz_queue_depth",
+            "gauge",
+            "Searches waiting for or running on the search thread",
+            format!("topaz_queue_depth {}", self.queue_depth),
+        );
+        metric(
+            "topaz_tinue_searches_total",
+            "counter",
+            "Tinue searches run",
+            format!("topaz_tinue_searches_total {}", self.tinue_searches),
+        );
+        metric(
+            "topaz_tinue_search_seconds_total",
+            "counter",
+            "Time spent in tinue searches",
+            format!(
+                "topaz_tinue_search_seconds_total {}",
+                self.tinue_time.as_secs_f64()
+            ),
+        );
+        metric(
+            "topaz_tinue_timeouts_total",
+            "counter",
+            "Tinue searches that hit their limit",
+            format!("topaz_tinue_timeouts_total {}", self.tinue_timeouts),
+        );
+        metric(
+            "topaz_active_puzzles",
+            "gauge",
+            "Puzzles being solved",
+            format!("topaz_active_puzzles {}", self.active_puzzles),
+        );
+        metric(
+            "topaz_active_games",
+            "gauge",
+            "Games being played against Topaz",
+            format!("topaz_active_games {}", self.active_games),
+        );
+        out
+    }
+}
+
+/// Serves `/metrics` in Prometheus format and `/status` as plain text
+pub async fn serve(addr: String) -> Result<()> {
+    let listener = TcpListener::bind(&addr).await?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream).await {
+                tracing::warn!("Error serving metrics: {}", e);
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream) -> Result<()> {
+    let (read, mut write) = stream.split();
+    let mut reader = BufReader::new(read);
+    let mut request = String::new();
+    reader.read_line(&mut request).await?;
+    // Drain the headers so the client sees a clean close
+    let mut line = String::new();
+    while reader.read_line(&mut line).await? > 2 {
+        line.clear();
+    }
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let (status, content_type, body) = match path {
+        "/metrics" => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            Snapshot::take().prometheus(),
+        ),
+        "/" | "/status" => (
+            "200 OK",
+            "text/plain; charset=utf-8",
+            Snapshot::take().status_text() + "\n",
+        ),
+        _ => (
+            "404 Not Found",
+            "text/plain; charset=utf-8",
+            String::from("Not found\n"),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    write.write_all(response.as_bytes()).await?;
+    write.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let snapshot = Snapshot {
+            uptime: Duration::from_secs(90_061),
+            requests: vec![("eval", 2), ("tinue", 3)],
+            tinue_searches: 4,
+            tinue_time: Duration::from_millis(1_000),
+            tinue_timeouts: 1,
+            active_puzzles: 2,
+            ..Snapshot::default()
+        };
+        let text = snapshot.status_text();
+        assert!(text.starts_with("Uptime 1d 1h 1m\nRequests: eval 2, tinue 3\n"));
+        assert!(text.contains("Tinue searches 4, average 250 ms, 1 timeouts"));
+        let prom = snapshot.prometheus();
+        assert!(prom.contains("# TYPE topaz_requests_total counter\n"));
+        assert!(prom.contains("topaz_requests_total{command=\"tinue\"} 3\n"));
+        assert!(prom.contains("topaz_tinue_search_seconds_total 1\n"));
+        assert!(prom.contains("topaz_active_puzzles 2\n"));
+    }
+}
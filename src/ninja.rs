@@ -0,0 +1,290 @@
+use anyhow::{anyhow, Result};
+use topaz_tak::{Color, TakBoard, TakGame};
+
+use super::annotate::{self, Annotation, StartPosition};
+use super::decompress_uri;
+
+/// Links to ptn as it is, for ptn that is already complete
+pub fn link(ptn: &str, name: &str) -> String {
+    format!(
+        "https://ptn.ninja/{}&name={}",
+        lz_str::compress_to_encoded_uri_component(ptn),
+        lz_str::compress_to_encoded_uri_component(name)
+    )
+}
+
+/// Builds a ptn.ninja link to a position, with optional moves, branches and view options
+#[derive(Debug, Clone)]
+pub struct LinkBuilder {
+    size: usize,
+    tps: String,
+    komi: u8,
+    start: StartPosition,
+    name: String,
+    white: Option<String>,
+    black: Option<String>,
+    opening: Option<String>,
+    moves: Vec<String>,
+    notes: Vec<Annotation>,
+    /// Alternative lines, each starting after this many plies of the main line
+    variations: Vec<(usize, Vec<String>)>,
+    ply: Option<usize>,
+    flip: bool,
+    show_ptn: bool,
+}
+
+impl LinkBuilder {
+    /// Starts from the current position of the game, or `None` for an unsupported size
+    pub fn new(game: &TakGame) -> Option<Self> {
+        let (size, tps, komi) = match game {
+            TakGame::Standard5(board) => (5, format!("{:?}", board), board.komi()),
+            TakGame::Standard6(board) => (6, format!("{:?}", board), board.komi()),
+            TakGame::Standard7(board) => (7, format!("{:?}", board), board.komi()),
+            _ => return None,
+        };
+        Some(Self {
+            size,
+            tps,
+            komi,
+            start: StartPosition::of(game),
+            name: String::from("Topaz"),
+            white: None,
+            black: None,
+            opening: None,
+            moves: Vec::new(),
+            notes: Vec::new(),
+            variations: Vec::new(),
+            ply: None,
+            flip: false,
+            show_ptn: false,
+        })
+    }
+    /// Title shown by ptn.ninja
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+    pub fn players(mut self, white: &str, black: &str) -> Self {
+        self.white = Some(white.to_string());
+        self.black = Some(black.to_string());
+        self
+    }
+    /// Opening rule such as "no-swap", ptn.ninja assumes "swap" otherwise
+    pub fn opening(mut self, opening: &str) -> Self {
+        self.opening = Some(opening.to_string());
+        self
+    }
+    /// The main line, played from the position
+    pub fn moves(mut self, moves: Vec<String>) -> Self {
+        self.notes = vec![Annotation::default(); moves.len()];
+        self.moves = moves;
+        self
+    }
+    /// Marks and comments for the main line, one per move
+    pub fn notes(mut self, notes: Vec<Annotation>) -> Self {
+        self.notes = notes;
+        self.notes.resize(self.moves.len(), Annotation::default());
+        self
+    }
+    /// Comments on a move of the main line, counted from 0
+    pub fn comment(mut self, ply: usize, comment: &str) -> Self {
+        if let Some(note) = self.notes.get_mut(ply) {
+            note.comment = Some(comment.to_string());
+        }
+        self
+    }
+    /// Adds a branch replacing the main line from `ply` on
+    pub fn variation(mut self, ply: usize, moves: Vec<String>) -> Self {
+        self.variations.push((ply.min(self.moves.len()), moves));
+        self
+    }
+    /// Opens the link after this many plies of the main line
+    pub fn ply(mut self, ply: usize) -> Self {
+        self.ply = Some(ply);
+        self
+    }
+    /// Shows the board from black's side
+    pub fn flip(mut self, flip: bool) -> Self {
+        self.flip = flip;
+        self
+    }
+    /// Opens the ptn panel next to the board
+    pub fn show_ptn(mut self, show: bool) -> Self {
+        self.show_ptn = show;
+        self
+    }
+    fn tags(&self) -> String {
+        let mut tags = String::new();
+        if let Some(ref white) = self.white {
+            tags.push_str(&format!("[Player1 \"{}\"]\n", white));
+        }
+        if let Some(ref black) = self.black {
+            tags.push_str(&format!("[Player2 \"{}\"]\n", black));
+        }
+        tags.push_str(&format!(
+            "[Size \"{}\"]\n[Komi \"{}\"]\n[TPS \"{}\"]",
+            self.size,
+            self.komi as f32 / 2.0,
+            self.tps
+        ));
+        if let Some(ref opening) = self.opening {
+            tags.push_str(&format!("\n[Opening \"{}\"]", opening));
+        }
+        tags
+    }
+    /// The ptn behind the link
+    pub fn ptn(&self) -> String {
+        let mut ptn = format!("{}\n\n", self.tags());
+        ptn.push_str(&annotate::move_text(&self.moves, &self.notes, &self.start));
+        // ptn.ninja turns each line that repeats a move number into a branch
+        for (ply, moves) in self.variations.iter() {
+            let notes = vec![Annotation::default(); moves.len()];
+            ptn.push('\n');
+            ptn.push_str(&annotate::move_text(
+                moves,
+                &notes,
+                &after_plies(&self.start, *ply),
+            ));
+        }
+        ptn
+    }
+    pub fn build(&self) -> String {
+        let mut url = link(&self.ptn(), &self.name);
+        if let Some(ply) = self.ply {
+            url.push_str(&format!("&ply={}", ply));
+        }
+        if self.flip {
+            url.push_str("&flip=true");
+        }
+        if self.show_ptn {
+            url.push_str("&showPTN=true");
+        }
+        url
+    }
+}
+
+/// The `ply` a link opens at, `12!` meaning after ply 12 rather than before it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlyParam {
+    pub index: usize,
+    pub after: bool,
+}
+
+impl PlyParam {
+    fn parse(value: &str) -> Option<Self> {
+        let (index, after) = match value.strip_suffix('!') {
+            Some(index) => (index, true),
+            None => (value, false),
+        };
+        Some(Self {
+            index: index.parse().ok()?,
+            after,
+        })
+    }
+    /// How many plies of the game are played in the position shown
+    pub fn played(&self) -> usize {
+        self.index + self.after as usize
+    }
+}
+
+/// Everything in a ptn.ninja link
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NinjaLink {
+    pub ptn: String,
+    pub name: Option<String>,
+    pub ply: Option<PlyParam>,
+    pub target_branch: Option<String>,
+    pub theme: Option<String>,
+}
+
+/// Decodes the part of a link after `ptn.ninja/`, ignoring parameters it doesn't know
+pub fn decode(link: &str) -> Result<NinjaLink> {
+    let mut parts = link.trim().split('&');
+    let ptn = parts.next().unwrap_or("");
+    let ptn = decompress_uri(ptn).ok_or_else(|| anyhow!("Bad ptn ninja game string"))?;
+    let mut decoded = NinjaLink {
+        ptn,
+        ..NinjaLink::default()
+    };
+    for part in parts {
+        let (key, value) = part.split_once('=').unwrap_or((part, ""));
+        // The name and theme are compressed like the ptn, other values are plain
+        let text = || decompress_uri(value).filter(|x| !x.is_empty());
+        match key {
+            "name" => decoded.name = text(),
+            "ply" => decoded.ply = PlyParam::parse(value),
+            "targetBranch" => decoded.target_branch = Some(value.to_string()),
+            "theme" => decoded.theme = text().or_else(|| Some(value.to_string())),
+            _ => tracing::debug!("Ignoring ptn.ninja parameter {}", key),
+        }
+    }
+    Ok(decoded)
+}
+
+/// Where a line starts after some plies from the start
+fn after_plies(start: &StartPosition, plies: usize) -> StartPosition {
+    let offset = match start.color {
+        Color::White => 0,
+        Color::Black => 1,
+    };
+    let total = offset + plies;
+    StartPosition {
+        move_num: start.move_num + total / 2,
+        color: if total % 2 == 0 {
+            Color::White
+        } else {
+            Color::Black
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use topaz_tak::board::Board5;
+
+    #[test]
+    fn builds_links() {
+        let game = TakGame::Standard5(Board5::new().with_komi(4));
+        let moves = vec![String::from("a1"), String::from("e5"), String::from("c3")];
+        let builder = LinkBuilder::new(&game)
+            .unwrap()
+            .name("Test")
+            .players("Alice", "Bob")
+            .moves(moves)
+            .comment(2, "Center")
+            .variation(2, vec![String::from("b2")])
+            .variation(1, vec![String::from("d4"), String::from("c3")]);
+        let ptn = builder.ptn();
+        assert!(
+            ptn.starts_with("[Player1 \"Alice\"]\n[Player2 \"Bob\"]\n[Size \"5\"]\n[Komi \"2\"]")
+        );
+        assert!(ptn.ends_with("1. a1 e5\n2. c3 {Center}\n\n2. b2\n\n1. -- d4\n2. c3\n"));
+        let url = builder.clone().ply(2).flip(true).show_ptn(true).build();
+        assert!(url.starts_with(&link(&ptn, "Test")));
+        assert!(url.ends_with("&ply=2&flip=true&showPTN=true"));
+        assert_eq!(builder.build(), link(&ptn, "Test"));
+    }
+
+    #[test]
+    fn decodes_links() {
+        let game = TakGame::Standard5(Board5::new());
+        let url = LinkBuilder::new(&game)
+            .unwrap()
+            .name("Opened")
+            .moves(vec![String::from("a1"), String::from("e5")])
+            .ply(1)
+            .build();
+        let decoded = decode(url.split("ptn.ninja/").nth(1).unwrap()).unwrap();
+        assert!(decoded.ptn.ends_with("1. a1 e5\n"));
+        assert_eq!(decoded.name.as_deref(), Some("Opened"));
+        assert_eq!(decoded.ply.unwrap().played(), 1);
+        let after = PlyParam::parse("30!").unwrap();
+        assert_eq!((after.index, after.after, after.played()), (30, true, 31));
+        let ptn = lz_str::compress_to_encoded_uri_component("[Size \"6\"]");
+        let decoded = decode(&format!("{}&targetBranch=12-1&ply=x", ptn)).unwrap();
+        assert_eq!(decoded.target_branch.as_deref(), Some("12-1"));
+        assert_eq!(decoded.ply, None);
+        assert!(decode("!!!").is_err());
+    }
+}
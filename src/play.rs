@@ -1,340 +1,752 @@
 use std::collections::HashMap;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use topaz_tak::{
     eval::{Evaluator, Weights5, Weights6},
     search::{search, SearchInfo, SearchOutcome},
-    Position, TakGame, TimeBank,
+    GameResult, TimeBank,
 };
 
 use super::*;
-use serenity::model::id::{ChannelId, UserId};
-
-pub const TOPAZ_ID: UserId = UserId(211376698778714123);
-pub const TAK_BOT_ID: UserId = UserId(793658103668539424);
-const MAX_DEPTH: usize = 20;
-const GOAL_TIME: u64 = 20_000;
-
-static LINK_START: &'static str = "<https://ptn.ninja/";
-
-#[derive(Default)]
-pub struct Matches {
-    pub matches: HashMap<ChannelId, AsyncGameState>,
-}
-
-impl Matches {
-    pub fn update_rooms(&mut self, discord: &Discord) -> Result<()> {
-        std::thread::sleep(Duration::from_secs(5));
-        let channels = discord
-            .get_server_channels(TAK_TALK)
-            .expect("Failed to get Tak server channels!");
-        for game in channels
-            .iter()
-            .filter_map(|chan| AsyncGameState::try_new(chan))
-        {
-            self.track_room(discord, game)?;
-        }
-        dbg!(self.matches.len());
-        Ok(())
-    }
-    pub fn track_room(&mut self, discord: &Discord, mut game: AsyncGameState) -> Result<()> {
-        // If we are not yet tracking this game room
-        if !self.matches.contains_key(&game.channel_id) {
-            let messages = discord.get_messages(
-                game.channel_id,
-                discord::GetMessages::MostRecent,
-                Some(16),
-            )?;
-            // If we didn't get the full information that we needed request a link
-            if game.search_room(&messages).is_none() {
-                game.request_link(discord)?;
-            } else {
-                game.make_move(discord).expect("Unable to make move!");
+
+/// Multi-pv first scores every root move with a shallow search
+const CANDIDATE_DEPTH: usize = 3;
+const CANDIDATE_TIME: u64 = 50;
+/// Score given to a root move that ends the game in the mover's favor
+const DECIDED: i32 = 100_000;
+
+/// How hard Topaz tries when choosing a move in a bot game
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strength {
+    Beginner,
+    Casual,
+    Intermediate,
+    Strong,
+    Full,
+}
+
+struct StrengthLimits {
+    max_depth: usize,
+    goal_time: u64,
+    tinue_nodes: usize,
+    /// Eval noise is added up to and including this move number
+    noise_until: usize,
+}
+
+impl Strength {
+    fn limits(&self) -> StrengthLimits {
+        let (max_depth, goal_time, tinue_nodes, noise_until) = match self {
+            Strength::Beginner => (2, 1_000, 0, usize::MAX),
+            Strength::Casual => (3, 2_000, 5_000, usize::MAX),
+            Strength::Intermediate => (5, 5_000, 50_000, 10),
+            Strength::Strong => (8, 10_000, 200_000, 6),
+            Strength::Full => {
+                let search = &config().search;
+                (
+                    search.eval_depth,
+                    search.eval_time_ms,
+                    search.tinue_nodes * 5,
+                    6,
+                )
             }
-            self.matches.insert(game.channel_id, game);
+        };
+        StrengthLimits {
+            max_depth,
+            goal_time,
+            tinue_nodes,
+            noise_until,
         }
-        Ok(())
     }
-    pub fn untrack_room(&mut self, discord: &Discord, chan: &PublicChannel) -> Result<()> {
-        let _ = self.matches.remove(&chan.id);
-        Ok(())
+}
+
+impl FromStr for Strength {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let strength = match s.to_ascii_lowercase().as_str() {
+            "beginner" => Strength::Beginner,
+            "casual" => Strength::Casual,
+            "intermediate" => Strength::Intermediate,
+            "strong" => Strength::Strong,
+            "full" => Strength::Full,
+            _ => anyhow::bail!("Unknown strength {}", s),
+        };
+        Ok(strength)
+    }
+}
+
+impl std::fmt::Display for Strength {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Strength::Beginner => "Beginner",
+            Strength::Casual => "Casual",
+            Strength::Intermediate => "Intermediate",
+            Strength::Strong => "Strong",
+            Strength::Full => "Full",
+        };
+        write!(f, "{}", s)
     }
 }
 
-pub struct AsyncGameState {
-    pub channel_id: ChannelId,
-    pub board: Option<TakGame>,
-    player1: String,
-    player2: String,
-    undo_request: bool,
+lazy_static! {
+    static ref ACTIVE_GAMES: Arc<Mutex<HashMap<u64, BotGame>>> =
+        Arc::new(Mutex::new(HashMap::new()));
 }
 
-impl AsyncGameState {
-    pub fn try_new(channel: &PublicChannel) -> Option<Self> {
-        let mut iter = channel.name.split("-🆚-");
-        let p1 = iter.next()?;
-        let p2 = iter.next()?;
-        if iter.next().is_some() {
-            None
+/// A game between a Discord user and Topaz, played in its own thread.
+pub struct BotGame {
+    player: u64,
+    player_name: String,
+    size: usize,
+    komi: u8,
+    topaz_color: Color,
+    strength: Strength,
+    moves: Vec<GameMove>,
+    thinking: bool,
+    started: SystemTime,
+}
+
+struct Challenge {
+    size: usize,
+    komi: u8,
+    topaz_color: Color,
+    strength: Strength,
+}
+
+impl BotGame {
+    fn new(player: u64, player_name: String, challenge: Challenge) -> Self {
+        Self {
+            player,
+            player_name,
+            size: challenge.size,
+            komi: challenge.komi,
+            topaz_color: challenge.topaz_color,
+            strength: challenge.strength,
+            moves: Vec::new(),
+            thinking: false,
+            started: SystemTime::now(),
+        }
+    }
+    fn side_to_move(&self) -> Color {
+        if self.moves.len() % 2 == 0 {
+            Color::White
         } else {
-            if p1 == TOPAZ || p2 == TOPAZ {
-                Some(Self {
-                    channel_id: channel.id,
-                    board: None,
-                    player1: p1.to_string(),
-                    player2: p2.to_string(),
-                    undo_request: false,
-                })
-            } else {
-                None
-            }
+            Color::Black
         }
     }
-    pub fn topaz_turn(&self) -> Option<bool> {
-        let board = self.board.as_ref()?;
-        let color = board.side_to_move();
-        let b = match color {
-            Color::White => TOPAZ == &self.player1,
-            Color::Black => TOPAZ == &self.player2,
+    fn topaz_turn(&self) -> bool {
+        self.side_to_move() == self.topaz_color
+    }
+    fn build_board(&self) -> TakGame {
+        let mut game = empty_game(self.size, self.komi).expect("Size is checked on creation");
+        for mv in self.moves.iter() {
+            game.do_move(*mv);
+        }
+        game
+    }
+    fn players(&self) -> (&str, &str) {
+        match self.topaz_color {
+            Color::White => ("Topaz", self.player_name.as_str()),
+            Color::Black => (self.player_name.as_str(), "Topaz"),
+        }
+    }
+    /// Tries to play the user's move, returning its canonical ptn if it was legal
+    fn play_user_move(&mut self, ptn_move: &str) -> Option<String> {
+        let game = self.build_board();
+        let mv = parse_move(ptn_move, self.size, game.side_to_move())?;
+        let (mv, ptn) = match game {
+            TakGame::Standard5(board) => checked_move(&board, mv)?,
+            TakGame::Standard6(board) => checked_move(&board, mv)?,
+            _ => return None,
         };
-        Some(b)
-    }
-    pub fn invalidate_board(&mut self) {
-        self.board = None;
-    }
-    pub fn do_message(&mut self, message: &Message, discord: &Discord) {
-        if message.content.starts_with("Your turn ") {
-            if message
-                .mentions
-                .iter()
-                .find(|x| x.id == play::TOPAZ_ID)
-                .is_some()
-            {
-                if let Some(true) = self.topaz_turn() {
-                    let new_board = match self.board.take().unwrap() {
-                        TakGame::Standard5(board) => TakGame::Standard5(
-                            play_async_move::<Weights5>(board, message.channel_id, &discord)
-                                .expect("Failed to send message"),
-                        ),
-                        TakGame::Standard6(board) => TakGame::Standard6(
-                            play_async_move::<Weights6>(board, message.channel_id, &discord)
-                                .expect("Failed to send message"),
-                        ),
-                        _ => todo!(),
-                    };
-                    self.board = Some(new_board);
-                } else {
-                    self.request_link(discord).unwrap();
-                }
+        self.moves.push(mv);
+        Some(ptn)
+    }
+    /// Takes back the user's last move along with Topaz's reply to it
+    fn undo(&mut self) -> bool {
+        let user_moves = if self.topaz_color == Color::White {
+            self.moves.len() / 2
+        } else {
+            (self.moves.len() + 1) / 2
+        };
+        if user_moves == 0 {
+            return false;
+        }
+        if self.topaz_turn() {
+            self.moves.pop();
+        } else {
+            self.moves.pop();
+            self.moves.pop();
+        }
+        true
+    }
+    fn result(&self) -> Option<String> {
+        match self.build_board() {
+            TakGame::Standard5(board) => result_string(&board),
+            TakGame::Standard6(board) => result_string(&board),
+            _ => None,
+        }
+    }
+    fn to_ptn(&self, result: &str) -> String {
+        let (white, black) = self.players();
+        let mut ptn = format!(
+            "[Site \"Discord\"]\n[Player1 \"{}\"]\n[Player2 \"{}\"]\n[Date \"{}\"]\n[Size \"{}\"]\n[Komi \"{}\"]\n[Result \"{}\"]\n\n",
+            white,
+            black,
+            ptn_date(self.started),
+            self.size,
+            self.komi as f32 / 2.0,
+            result
+        );
+        let moves: Vec<_> = match self.size {
+            5 => self.moves.iter().map(|m| m.to_ptn::<Board5>()).collect(),
+            _ => self.moves.iter().map(|m| m.to_ptn::<Board6>()).collect(),
+        };
+        for (idx, pair) in moves.chunks(2).enumerate() {
+            ptn.push_str(&format!("{}. {}\n", idx + 1, pair.join(" ")));
+        }
+        ptn.push_str(result);
+        ptn.push('\n');
+        ptn
+    }
+    async fn archive(&self, ptn: &str) -> Result<()> {
+        let dir = std::path::Path::new(&config().paths.game_archive);
+        tokio::fs::create_dir_all(dir).await?;
+        let (white, black) = self.players();
+        let file_safe = |s: &str| -> String {
+            s.chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect()
+        };
+        let name = format!(
+            "{}-{}-vs-{}.ptn",
+            self.started.duration_since(UNIX_EPOCH)?.as_secs(),
+            file_safe(white),
+            file_safe(black)
+        );
+        tokio::fs::write(dir.join(name), ptn).await?;
+        Ok(())
+    }
+    /// Archives the finished game and builds the summary to post in its thread.
+    /// The game must already be out of `ACTIVE_GAMES`, so no lock is held while writing.
+    async fn finish(self, result: &str) -> String {
+        let ptn = self.to_ptn(result);
+        if let Err(e) = self.archive(&ptn).await {
+            tracing::warn!("Failed to archive game: {}", e);
+        }
+        let (white, black) = self.players();
+        let name = format!("{} vs {}", white, black);
+        format!(
+            "Game over: {} after {} plies. Result {}\n{}",
+            name,
+            self.moves.len(),
+            result,
+            ninja::link(&ptn, &name)
+        )
+    }
+}
+
+/// Formats the date part of a timestamp the way ptn expects, e.g. 2022.10.31
+fn ptn_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    // Days since the epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{}.{:02}.{:02}", year, month, day)
+}
+
+/// Marks a move onto a wall as a crush and checks that it is legal
+pub(crate) fn checked_move<B: TakBoard>(board: &B, mut mv: GameMove) -> Option<(GameMove, String)> {
+    match board.board().get(mv.dest_sq(B::SIZE)).and_then(|x| x.top()) {
+        Some(topaz_tak::Piece::WhiteWall) | Some(topaz_tak::Piece::BlackWall) => {
+            mv = mv.set_crush();
+        }
+        _ => {}
+    }
+    if !board.legal_move(mv) {
+        return None;
+    }
+    Some((mv, mv.to_ptn::<B>()))
+}
+
+fn result_string<B: TakBoard>(board: &B) -> Option<String> {
+    let s = match board.game_result()? {
+        GameResult::WhiteWin if board.road(Color::White) => "R-0",
+        GameResult::WhiteWin => "F-0",
+        GameResult::BlackWin if board.road(Color::Black) => "0-R",
+        GameResult::BlackWin => "0-F",
+        GameResult::Draw => "1/2-1/2",
+    };
+    Some(s.to_string())
+}
+
+fn parse_challenge(args: &[&str]) -> Result<Challenge> {
+    let mut size = 6;
+    let mut user_color = if rand::random() {
+        Color::White
+    } else {
+        Color::Black
+    };
+    let mut komi = 0;
+    let mut strength = Strength::Full;
+    let mut iter = args.iter().map(|x| x.to_ascii_lowercase());
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "white" | "w" => user_color = Color::White,
+            "black" | "b" => user_color = Color::Black,
+            "random" => {}
+            "komi" => {
+                let value = iter.next().ok_or_else(|| anyhow!("Missing komi value"))?;
+                komi = parse_komi(&value).ok_or_else(|| anyhow!("Bad komi {}", value))?;
+            }
+            "level" => {
+                let value = iter.next().ok_or_else(|| anyhow!("Missing level"))?;
+                strength = value.parse()?;
             }
-        } else if message.content.starts_with("!tak undo") {
-            if self.undo_request {
-                if message.author.id != TOPAZ_ID {
-                    self.undo_request = false;
+            _ => {
+                if let Ok(level) = arg.parse() {
+                    strength = level;
+                } else {
+                    size = arg
+                        .parse()
+                        .map_err(|_| anyhow!("Could not interpret {}", arg))?;
                 }
             }
-            self.invalidate_board();
-        } else if message.content.starts_with("!tak rematch") {
-            self.invalidate_board();
-        } else if message.content.starts_with("Invalid move.") {
-            self.invalidate_board();
-        } else if message.content.starts_with("You are not ") {
-            self.invalidate_board();
-        } else if message.content.starts_with("!topaz undo") {
-            self.undo_request = true;
-            std::thread::sleep(Duration::from_secs(2));
-            discord
-                .send_message(message.channel_id, "!tak undo", "", false)
-                .unwrap();
-        } else if message.author.id == TAK_BOT_ID && message.content.starts_with(LINK_START) {
-            let link: String = message
-                .content
-                .chars()
-                .filter(|&c| c != '<' && c != '>')
-                .collect();
-            self.board = handle_link(&link);
-            self.make_move(discord).expect("Unable to make move!");
-        } else if message.content.starts_with("!topaz position") {
-            let s = format!("This is the position, right? \n{}", debug_tps(&self.board));
-            discord
-                .send_message(message.channel_id, &s, "", false)
-                .expect("Failed to send message!");
-        } else if message.content.starts_with("!topaz search")
-            || message.content.starts_with("!topaz analyze")
-        {
-            if let Some(ref mut board) = self.board {
-                let res = match board {
-                    TakGame::Standard5(board) => format!("{}", analyze_pos::<Weights5>(board)),
-                    TakGame::Standard6(board) => format!("{}", analyze_pos::<Weights6>(board)),
-                    _ => todo!(),
-                };
-                discord
-                    .send_message(message.channel_id, &format!("{}", res), "", false)
-                    .unwrap();
+        }
+    }
+    if size != 5 && size != 6 {
+        anyhow::bail!("Topaz only plays 5x5 and 6x6 games");
+    }
+    Ok(Challenge {
+        size,
+        komi,
+        topaz_color: !user_color,
+        strength,
+    })
+}
+
+/// Starts a new game against Topaz in a thread off of the challenge message
+pub async fn handle_play_req(
+    frontend: &dyn ChatFrontend,
+    request: &Request,
+    args: &[&str],
+) -> Result<()> {
+    let challenge = match parse_challenge(args) {
+        Ok(x) => x,
+        Err(e) => {
+            let example = "!topaz play 6 black komi 2 level casual";
+            frontend.reply(&format!("{}. Try {}", e, example)).await?;
+            return Ok(());
+        }
+    };
+    let game = BotGame::new(request.user, request.user_name.clone(), challenge);
+    let (white, black) = game.players();
+    let thread_name = format!("{} vs {} {}x{}", white, black, game.size, game.size);
+    let thread = frontend.create_thread(&thread_name).await?;
+    let intro = format!(
+        "{} vs {}, komi {}, level {}. Type your moves in ptn, !topaz undo to take one back or !topaz resign to give up.",
+        white,
+        black,
+        game.komi as f32 / 2.0,
+        game.strength,
+    );
+    frontend.send(thread, &intro).await?;
+    let topaz_first = game.topaz_turn();
+    {
+        let mut locked = ACTIVE_GAMES.lock().expect("Lock is not poisoned");
+        locked.insert(thread, game);
+    }
+    if topaz_first {
+        topaz_reply(frontend, thread).await?;
+    }
+    Ok(())
+}
+
+pub fn is_game_channel(channel: u64) -> bool {
+    ACTIVE_GAMES.lock().unwrap().contains_key(&channel)
+}
+
+pub fn active_game_count() -> usize {
+    ACTIVE_GAMES.lock().unwrap().len()
+}
+
+/// Handles a message sent in a game thread, either a move or a game command
+pub async fn handle_game_message(frontend: &dyn ChatFrontend, request: &Request) -> Result<()> {
+    let channel = request.channel;
+    // No reply means the user made a move and it is now Topaz's turn
+    let reply: Option<GameReply> = {
+        let mut locked = ACTIVE_GAMES.lock().unwrap();
+        let game = match locked.get_mut(&channel) {
+            Some(game) if game.player == request.user => game,
+            _ => return Ok(()),
+        };
+        let content = request.content.trim();
+        if content.starts_with("!topaz resign") {
+            let result = match game.topaz_color {
+                Color::White => "1-0",
+                Color::Black => "0-1",
+            };
+            let game = locked.remove(&channel).expect("Game is active");
+            let text = format!("{} resigns.", game.player_name);
+            Some(GameReply::Finished(text, game, result.to_string()))
+        } else if game.thinking {
+            Some(GameReply::text("Please wait, I am still thinking."))
+        } else if content.starts_with("!topaz undo") {
+            if game.undo() {
+                Some(GameReply::text("Undo completed. Your move."))
             } else {
-                discord
-                    .send_message(
-                        message.channel_id,
-                        "Sorry I don't know the board state right now.",
-                        "",
-                        false,
-                    )
-                    .unwrap();
+                Some(GameReply::text("There is nothing to undo."))
             }
         } else {
-            if let Some(ref mut board) = self.board {
-                if let Some(m) =
-                    super::parse_move(&message.content, get_size(board), board.side_to_move())
-                {
-                    board.do_move(m);
+            let ptn_str = clean_ptn_move(content.split_whitespace().next().unwrap_or(""));
+            if !PTN_MOVE.is_match(&ptn_str) {
+                return Ok(());
+            }
+            if game.topaz_turn() {
+                Some(GameReply::text("It is not your turn."))
+            } else if let Some(ptn) = game.play_user_move(&ptn_str) {
+                if let Some(result) = game.result() {
+                    let game = locked.remove(&channel).expect("Game is active");
+                    let text = format!("{} ends the game.", ptn);
+                    Some(GameReply::Finished(text, game, result))
+                } else {
+                    None
                 }
+            } else {
+                Some(GameReply::Text(format!(
+                    "Could not interpret {} as a legal ptn move",
+                    ptn_str
+                )))
             }
         }
-    }
-    pub fn make_move(&mut self, discord: &Discord) -> Option<()> {
-        let turn = self.topaz_turn()? && !self.undo_request;
-        if turn {
-            if self.board.as_ref()?.game_result().is_some() {
-                return Some(());
-            }
-            let new_board = match self.board.take().unwrap() {
-                TakGame::Standard5(board) => TakGame::Standard5(
-                    play_async_move::<Weights5>(board, self.channel_id, &discord)
-                        .expect("Failed to send message"),
-                ),
-                TakGame::Standard6(board) => TakGame::Standard6(
-                    play_async_move::<Weights6>(board, self.channel_id, &discord)
-                        .expect("Failed to send message"),
-                ),
-                _ => return None,
-            };
-            self.board = Some(new_board);
+    };
+    match reply {
+        Some(GameReply::Text(text)) => frontend.reply(&text).await?,
+        Some(GameReply::Finished(text, game, result)) => {
+            let summary = game.finish(&result).await;
+            frontend.reply(&format!("{}\n{}", text, summary)).await?;
         }
-        Some(())
+        None => topaz_reply(frontend, channel).await?,
     }
-    pub fn request_link(&self, discord: &Discord) -> Result<()> {
-        discord.send_message(self.channel_id, "!tak link", "", false)?;
-        std::thread::sleep(Duration::from_secs(3));
-        Ok(())
+    Ok(())
+}
+
+/// An answer to a message in a game thread, worked out while the games are locked
+enum GameReply {
+    Text(String),
+    /// The game ended and is out of `ACTIVE_GAMES`, the text goes before its summary
+    Finished(String, BotGame, String),
+}
+
+impl GameReply {
+    fn text(text: &str) -> Self {
+        GameReply::Text(text.to_string())
     }
-    pub fn search_room(&mut self, messages: &[Message]) -> Option<()> {
-        let game_link = find_link(messages)?;
-        let mut board = handle_link(&game_link)?;
-        let mut extra_moves = Vec::new();
-        let size = get_size(&board);
-        for message in messages.iter() {
-            if message.content.starts_with(LINK_START) {
-                break;
-            }
-            // The person may be discussing the move in the context of a longer sentence
-            if message.content.split_whitespace().nth(1).is_some() {
-                continue;
-            }
-            if super::parse_move(&message.content, size, board.side_to_move()).is_some() {
-                extra_moves.push(&message.content);
+}
+
+/// Searches for Topaz's move in the given game thread and plays it
+async fn topaz_reply(frontend: &dyn ChatFrontend, channel: u64) -> Result<()> {
+    let (board, strength) = {
+        let mut locked = ACTIVE_GAMES.lock().unwrap();
+        let game = locked
+            .get_mut(&channel)
+            .ok_or_else(|| anyhow!("Game is no longer active"))?;
+        game.thinking = true;
+        (game.build_board(), game.strength)
+    };
+    let found = metrics::blocking(move || choose_move(board, strength)).await?;
+    let reply = {
+        let mut locked = ACTIVE_GAMES.lock().unwrap();
+        let game = locked
+            .get_mut(&channel)
+            .ok_or_else(|| anyhow!("Game is no longer active"))?;
+        game.thinking = false;
+        let (mv, ptn) = found?;
+        game.moves.push(mv);
+        match game.result() {
+            Some(result) => {
+                let game = locked.remove(&channel).expect("Game is active");
+                GameReply::Finished(ptn, game, result)
             }
+            None => GameReply::Text(ptn),
         }
-        for mv in extra_moves.into_iter().rev() {
-            let m = super::parse_move(&mv, size, board.side_to_move()).unwrap();
-            board.do_move(m);
+    };
+    let reply = match reply {
+        GameReply::Finished(ptn, game, result) => {
+            format!("{}\n{}", ptn, game.finish(&result).await)
         }
-        self.board = Some(board);
-        Some(())
+        GameReply::Text(ptn) => ptn,
+    };
+    frontend.send(channel, &reply).await?;
+    Ok(())
+}
+
+fn analyze_pos<E: Evaluator + Default>(
+    board: &mut E::Game,
+    token: &jobs::CancelToken,
+) -> Result<SearchOutcome<E::Game>> {
+    if let Some(result) = result_string(board) {
+        return Err(anyhow!("The game is already over, {}", result));
     }
+    token.check()?;
+    let limits = &config().search;
+    let time = time_left(token, limits.eval_time_ms);
+    let mut info = SearchInfo::new(limits.eval_depth, 2 << 20).time_bank(TimeBank::flat(time));
+    let eval = E::default();
+    let outcome = search(board, &eval, &mut info).ok_or_else(|| anyhow!("Search found no move"))?;
+    // A cancel during the search only takes effect once it returns
+    token.check()?;
+    Ok(outcome)
 }
 
-fn get_size(game: &TakGame) -> usize {
+/// A search can't be interrupted, so it gets no more time than the job has left
+fn time_left(token: &jobs::CancelToken, time: u64) -> u64 {
+    token
+        .remaining()
+        .map_or(time, |x| time.min(x.as_millis() as u64))
+}
+
+/// Runs a full strength search on the position, within the job's deadline
+pub fn evaluate_position(game: TakGame, token: &jobs::CancelToken) -> Result<EngineLine> {
     match game {
-        TakGame::Standard5(_) => Board5::SIZE,
-        TakGame::Standard6(_) => Board6::SIZE,
-        TakGame::Standard7(_) => Board7::SIZE,
-        _ => 6,
+        TakGame::Standard5(mut board) => {
+            let outcome = analyze_pos::<Weights5>(&mut board, token)?;
+            Ok(EngineLine::from_outcome(&outcome))
+        }
+        TakGame::Standard6(mut board) => {
+            let outcome = analyze_pos::<Weights6>(&mut board, token)?;
+            Ok(EngineLine::from_outcome(&outcome))
+        }
+        _ => Err(anyhow!("Unsupported board size")),
     }
 }
 
-fn analyze_pos<E: Evaluator + Default>(board: &mut E::Game) -> SearchOutcome<E::Game> {
-    let mut info = SearchInfo::new(MAX_DEPTH, 2 << 20).time_bank(TimeBank::flat(GOAL_TIME));
-    let eval = E::default();
-    let search_res = search(board, &eval, &mut info);
-    search_res.unwrap()
+/// Finds the best `count` moves in the position, each with its own line
+pub fn evaluate_multi_pv(
+    game: TakGame,
+    count: usize,
+    token: &jobs::CancelToken,
+) -> Result<Vec<EngineLine>> {
+    match game {
+        TakGame::Standard5(board) => multi_pv::<Weights5>(board, count, token),
+        TakGame::Standard6(board) => multi_pv::<Weights6>(board, count, token),
+        _ => Err(anyhow!("Unsupported board size")),
+    }
 }
 
-fn debug_tps(game: &Option<TakGame>) -> String {
-    if let Some(game) = game {
-        match game {
-            TakGame::Standard5(board) => format!("{:?}", board),
-            TakGame::Standard6(board) => format!("{:?}", board),
-            TakGame::Standard7(board) => format!("{:?}", board),
-            _ => "UNK".to_string(),
+/// Scores a root move by searching the position after it, from the mover's perspective
+fn search_root_move<E: Evaluator + Default>(
+    board: &mut E::Game,
+    mv: GameMove,
+    depth: usize,
+    time: u64,
+) -> EngineLine {
+    let mover = board.side_to_move();
+    let rev = board.do_move(mv);
+    let mut line = EngineLine::default();
+    match board.game_result() {
+        Some(GameResult::Draw) => {}
+        Some(GameResult::WhiteWin) if mover == Color::White => line.score = DECIDED,
+        Some(GameResult::BlackWin) if mover == Color::Black => line.score = DECIDED,
+        Some(_) => line.score = -DECIDED,
+        None => {
+            let mut info = SearchInfo::new(depth, 2 << 14).time_bank(TimeBank::flat(time));
+            if let Some(outcome) = search(board, &E::default(), &mut info) {
+                line = EngineLine::from_outcome(&outcome);
+                line.score = -line.score;
+            }
         }
-    } else {
-        return "None".to_string();
     }
+    line.pv.insert(0, mv.to_ptn::<E::Game>());
+    board.reverse_move(rev);
+    line
+}
+
+fn multi_pv<E: Evaluator + Default>(
+    mut board: E::Game,
+    count: usize,
+    token: &jobs::CancelToken,
+) -> Result<Vec<EngineLine>> {
+    if let Some(result) = result_string(&board) {
+        return Err(anyhow!("The game is already over, {}", result));
+    }
+    let mut moves = Vec::new();
+    generate_all_moves(&board, &mut moves);
+    let mut candidates: Vec<_> = moves
+        .into_iter()
+        .map(|mv| {
+            token.check()?;
+            let time = time_left(token, CANDIDATE_TIME);
+            let line = search_root_move::<E>(&mut board, mv, CANDIDATE_DEPTH, time);
+            Ok((mv, line.score))
+        })
+        .collect::<Result<_>>()?;
+    candidates.sort_by_key(|x| -x.1);
+    candidates.truncate(count);
+    let limits = &config().search;
+    let time = limits.eval_time_ms / count.max(1) as u64;
+    let mut lines: Vec<_> = candidates
+        .into_iter()
+        .map(|(mv, _)| {
+            token.check()?;
+            let time = time_left(token, time);
+            Ok(search_root_move::<E>(
+                &mut board,
+                mv,
+                limits.eval_depth,
+                time,
+            ))
+        })
+        .collect::<Result<_>>()?;
+    // A cancel during the last search only takes effect once it returns
+    token.check()?;
+    lines.sort_by_key(|x| -x.score);
+    Ok(lines)
+}
+
+/// The parts of a search result we report, read from its `info` line
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EngineLine {
+    pub depth: usize,
+    /// Score from the perspective of the side to move
+    pub score: i32,
+    pub nodes: usize,
+    pub pv: Vec<String>,
 }
 
-fn handle_link(game_link: &str) -> Option<TakGame> {
-    let ptn = super::get_ptn_string(&game_link).expect("Valid ptn string!");
-    let (mut game, moves) = super::parse_game(&ptn)?;
-    for m in moves {
-        game.do_move(m);
+impl EngineLine {
+    pub fn from_outcome<T: TakBoard>(outcome: &SearchOutcome<T>) -> Self {
+        Self::parse(&outcome.to_string())
+    }
+    fn parse(info: &str) -> Self {
+        let mut line = Self::default();
+        let mut words = info.split_whitespace();
+        while let Some(word) = words.next() {
+            match word {
+                "depth" => line.depth = words.next().and_then(|x| x.parse().ok()).unwrap_or(0),
+                "cp" => line.score = words.next().and_then(|x| x.parse().ok()).unwrap_or(0),
+                "nodes" => line.nodes = words.next().and_then(|x| x.parse().ok()).unwrap_or(0),
+                "pv" => {
+                    line.pv = words.map(|x| x.to_string()).collect();
+                    break;
+                }
+                _ => {}
+            }
+        }
+        line
     }
-    Some(game)
 }
 
-fn find_link(messages: &[Message]) -> Option<String> {
-    for message in messages.iter() {
-        println!("{:?}: {}", message.author, message.content);
-        if message.content.starts_with("!tak undo") {
-            break;
-        } else if message.content.starts_with("!tak rematch") {
-            break;
-        } else if message.content.starts_with("Invalid move.") {
-            break;
-        } else if message.content.starts_with("You are not ") {
+/// The engine's view of the position reached after `ply` plies
+#[derive(Debug, Clone)]
+pub struct PlyEval {
+    pub ply: usize,
+    /// Score from white's perspective
+    pub score: i32,
+    pub line: EngineLine,
+}
+
+/// Searches every position of a game, stopping early if the game ends
+pub fn evaluate_game(
+    game: TakGame,
+    moves: &[GameMove],
+    token: &jobs::CancelToken,
+) -> Result<Vec<PlyEval>> {
+    match game {
+        TakGame::Standard5(board) => evaluate_plies::<Weights5>(board, moves, token),
+        TakGame::Standard6(board) => evaluate_plies::<Weights6>(board, moves, token),
+        _ => Err(anyhow!("Unsupported board size")),
+    }
+}
+
+fn evaluate_plies<E: Evaluator + Default>(
+    mut board: E::Game,
+    moves: &[GameMove],
+    token: &jobs::CancelToken,
+) -> Result<Vec<PlyEval>> {
+    let eval = E::default();
+    let limits = &config().search;
+    let mut evals = Vec::new();
+    for ply in 0..=moves.len() {
+        if board.game_result().is_some() {
             break;
-        } else if message.author.id == TAK_BOT_ID && message.content.starts_with(LINK_START) {
-            let link = message
-                .content
-                .chars()
-                .filter(|&c| c != '<' && c != '>')
-                .collect();
-            return Some(link);
         }
+        token.check()?;
+        let mut info = SearchInfo::new(limits.analyze_depth, 2 << 20)
+            .time_bank(TimeBank::flat(limits.analyze_ply_ms));
+        if let Some(outcome) = search(&mut board, &eval, &mut info) {
+            let line = EngineLine::from_outcome(&outcome);
+            let score = match board.side_to_move() {
+                Color::White => line.score,
+                Color::Black => -line.score,
+            };
+            evals.push(PlyEval { ply, score, line });
+        }
+        if let Some(mv) = moves.get(ply) {
+            board.do_move(*mv);
+        }
+    }
+    Ok(evals)
+}
+
+/// Runs [`play_async_move`] with the evaluator matching the board size
+pub fn choose_move(game: TakGame, strength: Strength) -> Result<(GameMove, String)> {
+    match game {
+        TakGame::Standard5(board) => play_async_move::<Weights5>(board, strength),
+        TakGame::Standard6(board) => play_async_move::<Weights6>(board, strength),
+        _ => Err(anyhow!("Unsupported board size")),
     }
-    None
 }
 
+/// Chooses Topaz's move, returning it along with its ptn including tak and tinue marks
 pub fn play_async_move<E: Evaluator + Default>(
     mut board: E::Game,
-    channel: ChannelId,
-    discord: &Discord,
-) -> Result<E::Game> {
-    let mut tinue_search = TinueSearch::new(board).limit(NODE_LIMIT * 5).quiet();
-    let best_move = if Some(true) == tinue_search.is_tinue() {
+    strength: Strength,
+) -> Result<(GameMove, String)>
+where
+    E::Game: std::fmt::Debug,
+{
+    if let Some(mv) = OPENING_BOOK.get().and_then(|book| book.choose(&board)) {
+        return Ok((mv, mv.to_ptn::<E::Game>()));
+    }
+    let limits = strength.limits();
+    let mut tinue_search = TinueSearch::new(board).limit(limits.tinue_nodes).quiet();
+    // A zero node limit means this level does not look for tinue at all
+    let tinue = limits.tinue_nodes > 0 && Some(true) == tinue_search.is_tinue();
+    let best_move = if tinue {
         let pv_move = tinue_search.principal_variation().into_iter().next();
         board = tinue_search.board;
         if let Some(mv) = pv_move {
-            format!("{}\"", mv.to_ptn::<E::Game>())
+            (mv, format!("{}\"", mv.to_ptn::<E::Game>()))
         } else {
             // Maybe it's just one ply?
             let road_move = find_road_move(&mut board);
             if let Some(mv) = road_move {
-                mv.to_ptn::<E::Game>()
+                (mv, mv.to_ptn::<E::Game>())
             } else {
                 return Err(anyhow!("Failed getting tinue pv search / road move!"));
             }
         }
     } else {
         board = tinue_search.board;
-        let mut info = SearchInfo::new(MAX_DEPTH, 2 << 20).time_bank(TimeBank::flat(GOAL_TIME));
+        let mut info =
+            SearchInfo::new(limits.max_depth, 2 << 20).time_bank(TimeBank::flat(limits.goal_time));
         let mut eval = E::default();
-        // if board.move_num() <= 6 {
-        //     eval.add_noise();
-        // }
+        if board.move_num() <= limits.noise_until {
+            eval.add_noise();
+        }
         let best_move = search(&mut board, &eval, &mut info)
             .and_then(|x| x.best_move())
             .ok_or_else(|| anyhow!("No best move from game search!"))?;
-        let mv = GameMove::try_from_ptn(&best_move, &board).unwrap();
+        let mv = GameMove::try_from_ptn(&best_move, &board)
+            .ok_or_else(|| anyhow!("Search returned an invalid move {}", best_move))?;
         let rev = board.do_move(mv);
         board.null_move();
         let mut moves = Vec::new();
@@ -342,12 +754,51 @@ pub fn play_async_move<E: Evaluator + Default>(
         board.rev_null_move();
         board.reverse_move(rev);
         if tak {
-            format!("{}'", best_move)
+            (mv, format!("{}'", best_move))
         } else {
-            best_move
+            (mv, best_move)
         }
     };
-    std::thread::sleep(Duration::from_secs(5));
-    discord.send_message(channel, &best_move, "", false)?;
-    Ok(board)
+    Ok(best_move)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn engine_line() {
+        let line = EngineLine::parse("info depth 7 score cp -35 time 812 nodes 90210 pv c3 d4 Sd3");
+        assert_eq!(line.depth, 7);
+        assert_eq!(line.score, -35);
+        assert_eq!(line.nodes, 90210);
+        assert_eq!(line.pv, vec!["c3", "d4", "Sd3"]);
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(ptn_date(UNIX_EPOCH), "1970.01.01");
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(ptn_date(time), "2023.11.14");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(ptn_date(leap_day), "2000.02.29");
+    }
+
+    #[test]
+    fn finished_games() {
+        let road = topaz_tak::board::Board5::try_from_tps("1,1,1,1,1/x5/x5/x5/2,2,2,2,x 2 5");
+        let game = TakGame::Standard5(road.unwrap());
+        let err = evaluate_position(game, &jobs::CancelToken::new()).unwrap_err();
+        assert_eq!(err.to_string(), "The game is already over, R-0");
+    }
+
+    #[test]
+    fn cancelled_multi_pv() {
+        let token = jobs::CancelToken::new();
+        assert_eq!(time_left(&token, CANDIDATE_TIME), CANDIDATE_TIME);
+        token.cancel();
+        let board = topaz_tak::board::Board6::try_from_tps("x6/x6/x6/x6/x6/x6 1 1").unwrap();
+        assert!(evaluate_multi_pv(TakGame::Standard6(board), 3, &token).is_err());
+    }
 }
@@ -0,0 +1,633 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use topaz_tak::{Color, GameMove, TakGame};
+
+use super::play::{self, Strength};
+use super::{empty_game, parse_move};
+
+/// The server drops idle connections, so ping if nothing arrives for this long
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A move in playtak server notation, e.g. `P A1 C` or `M A1 A3 2 1`
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlaytakMove {
+    Place {
+        square: String,
+        piece: Option<char>,
+    },
+    Move {
+        from: String,
+        to: String,
+        drops: Vec<u32>,
+    },
+}
+
+impl PlaytakMove {
+    /// Parses the words of a server move, starting at the `P` or `M`
+    pub fn parse(words: &[&str]) -> Option<Self> {
+        match words {
+            ["P", square] => Some(PlaytakMove::Place {
+                square: square.to_ascii_uppercase(),
+                piece: None,
+            }),
+            ["P", square, piece] => {
+                let piece = match *piece {
+                    "W" => 'W',
+                    "C" => 'C',
+                    _ => return None,
+                };
+                Some(PlaytakMove::Place {
+                    square: square.to_ascii_uppercase(),
+                    piece: Some(piece),
+                })
+            }
+            ["M", from, to, drops @ ..] if !drops.is_empty() => {
+                let drops = drops
+                    .iter()
+                    .map(|x| x.parse().ok())
+                    .collect::<Option<Vec<u32>>>()?;
+                Some(PlaytakMove::Move {
+                    from: from.to_ascii_uppercase(),
+                    to: to.to_ascii_uppercase(),
+                    drops,
+                })
+            }
+            _ => None,
+        }
+    }
+    pub fn to_server(&self) -> String {
+        match self {
+            PlaytakMove::Place { square, piece } => match piece {
+                Some(piece) => format!("P {} {}", square, piece),
+                None => format!("P {}", square),
+            },
+            PlaytakMove::Move { from, to, drops } => {
+                let drops: Vec<_> = drops.iter().map(|x| x.to_string()).collect();
+                format!("M {} {} {}", from, to, drops.join(" "))
+            }
+        }
+    }
+    pub fn to_ptn(&self) -> Option<String> {
+        match self {
+            PlaytakMove::Place { square, piece } => {
+                let prefix = match piece {
+                    Some('W') => "S",
+                    Some('C') => "C",
+                    _ => "",
+                };
+                Some(format!("{}{}", prefix, square.to_ascii_lowercase()))
+            }
+            PlaytakMove::Move { from, to, drops } => {
+                let (from_col, from_row) = square(from)?;
+                let (to_col, to_row) = square(to)?;
+                let dir = if to_col > from_col {
+                    '>'
+                } else if to_col < from_col {
+                    '<'
+                } else if to_row > from_row {
+                    '+'
+                } else {
+                    '-'
+                };
+                let count: u32 = drops.iter().sum();
+                let count_str = if count > 1 {
+                    count.to_string()
+                } else {
+                    String::new()
+                };
+                let drop_str = if drops.len() > 1 {
+                    drops.iter().map(|x| x.to_string()).collect()
+                } else {
+                    String::new()
+                };
+                Some(format!(
+                    "{}{}{}{}",
+                    count_str,
+                    from.to_ascii_lowercase(),
+                    dir,
+                    drop_str
+                ))
+            }
+        }
+    }
+    pub fn from_ptn(ptn: &str) -> Option<Self> {
+        let ptn = ptn.trim_end_matches(|c| "'\"!?*".contains(c));
+        let mut chars = ptn.chars().peekable();
+        let piece = match chars.peek() {
+            Some('S') => Some('W'),
+            Some('C') => Some('C'),
+            _ => None,
+        };
+        if piece.is_some() {
+            chars.next();
+        }
+        let mut count = None;
+        if let Some(c) = chars.peek().and_then(|c| c.to_digit(10)) {
+            count = Some(c);
+            chars.next();
+        }
+        let col = chars.next().filter(|c| c.is_ascii_lowercase())?;
+        let row = chars.next().filter(|c| c.is_ascii_digit())?;
+        let from = format!("{}{}", col.to_ascii_uppercase(), row);
+        let dir = match chars.next() {
+            None if count.is_none() => {
+                return Some(PlaytakMove::Place {
+                    square: from,
+                    piece,
+                })
+            }
+            Some(dir) if piece.is_none() => dir,
+            _ => return None,
+        };
+        let mut drops = chars
+            .map(|c| c.to_digit(10))
+            .collect::<Option<Vec<u32>>>()?;
+        if drops.is_empty() {
+            drops.push(count.unwrap_or(1));
+        }
+        let (dx, dy) = match dir {
+            '>' => (1, 0),
+            '<' => (-1, 0),
+            '+' => (0, 1),
+            '-' => (0, -1),
+            _ => return None,
+        };
+        let steps = drops.len() as i32;
+        let to_col = (col as u8 as i32 + dx * steps) as u8 as char;
+        let to_row = row.to_digit(10)? as i32 + dy * steps;
+        let to = format!("{}{}", to_col.to_ascii_uppercase(), to_row);
+        Some(PlaytakMove::Move { from, to, drops })
+    }
+}
+
+fn square(s: &str) -> Option<(u8, u32)> {
+    let mut chars = s.chars();
+    let col = chars.next()?.to_ascii_lowercase();
+    let row = chars.as_str().parse().ok()?;
+    Some((col as u8 - b'a', row))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameStart {
+    pub id: u32,
+    pub size: usize,
+    pub white: String,
+    pub black: String,
+    pub color: Color,
+    /// Komi in half flats, as sent by the server
+    pub komi: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerMessage {
+    Welcome,
+    LoginPrompt,
+    LoggedIn(String),
+    GameStart(GameStart),
+    Move { game: u32, mv: PlaytakMove },
+    GameOver { game: u32, result: String },
+    Other(String),
+}
+
+impl ServerMessage {
+    pub fn parse(line: &str) -> Self {
+        let line = line.trim();
+        let words: Vec<_> = line.split_whitespace().collect();
+        let parsed = match words.as_slice() {
+            ["Welcome!"] => Some(ServerMessage::Welcome),
+            ["Login", "or", "Register"] => Some(ServerMessage::LoginPrompt),
+            ["Welcome", name] => Some(ServerMessage::LoggedIn(
+                name.trim_end_matches('!').to_string(),
+            )),
+            ["Game", "Start", rest @ ..] => parse_game_start(rest).map(ServerMessage::GameStart),
+            [game, "Over", result] => game_id(game).map(|game| ServerMessage::GameOver {
+                game,
+                result: result.to_string(),
+            }),
+            [game, rest @ ..] => game_id(game).and_then(|game| {
+                PlaytakMove::parse(rest).map(|mv| ServerMessage::Move { game, mv })
+            }),
+            _ => None,
+        };
+        parsed.unwrap_or_else(|| ServerMessage::Other(line.to_string()))
+    }
+}
+
+fn game_id(word: &str) -> Option<u32> {
+    word.strip_prefix("Game#")?.parse().ok()
+}
+
+/// Parses `<id> <size> <white> vs <black> <color> <time> [komi] ...`
+fn parse_game_start(words: &[&str]) -> Option<GameStart> {
+    match words {
+        [id, size, white, "vs", black, color, _time, rest @ ..] => {
+            let color = match *color {
+                "white" => Color::White,
+                "black" => Color::Black,
+                _ => return None,
+            };
+            let komi = match rest.first() {
+                Some(komi) => komi.parse().ok()?,
+                None => 0,
+            };
+            Some(GameStart {
+                id: id.parse().ok()?,
+                size: size.parse().ok()?,
+                white: white.to_string(),
+                black: black.to_string(),
+                color,
+                komi,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// The parameters of a seek posted to the server
+#[derive(Debug, Clone)]
+pub struct Seek {
+    pub size: usize,
+    /// Starting time in seconds
+    pub time: u32,
+    /// Increment in seconds
+    pub increment: u32,
+    /// The color Topaz wants, or `None` for either
+    pub color: Option<Color>,
+    /// Komi in half flats
+    pub komi: u8,
+    pub flats: u32,
+    pub caps: u32,
+}
+
+impl Seek {
+    pub fn new(size: usize) -> Self {
+        let (flats, caps) = match size {
+            5 => (21, 1),
+            _ => (30, 1),
+        };
+        Self {
+            size,
+            time: 600,
+            increment: 10,
+            color: None,
+            komi: 0,
+            flats,
+            caps,
+        }
+    }
+    fn to_server(&self) -> String {
+        let color = match self.color {
+            Some(Color::White) => "W",
+            Some(Color::Black) => "B",
+            None => "A",
+        };
+        format!(
+            "Seek {} {} {} {} {} {} {} 0 0",
+            self.size, self.time, self.increment, color, self.komi, self.flats, self.caps
+        )
+    }
+}
+
+pub struct PlaytakClient {
+    /// `next_line` keeps a partial line between calls, so a read can be timed out safely
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl PlaytakClient {
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        })
+    }
+    pub async fn send(&mut self, line: &str) -> Result<()> {
+        tracing::debug!("Playtak send: {}", line);
+        self.writer.write_all(line.as_bytes()).await?;
+        self.writer.write_all(b"\n").await?;
+        Ok(())
+    }
+    pub async fn next_message(&mut self) -> Result<ServerMessage> {
+        let line = match self.lines.next_line().await? {
+            Some(line) => line,
+            None => anyhow::bail!("Playtak server closed the connection"),
+        };
+        tracing::debug!("Playtak recv: {}", line.trim_end());
+        Ok(ServerMessage::parse(&line))
+    }
+    pub async fn login(&mut self, name: &str, password: &str) -> Result<()> {
+        while self.next_message().await? != ServerMessage::LoginPrompt {}
+        self.send(&format!("Login {} {}", name, password)).await?;
+        loop {
+            match self.next_message().await? {
+                ServerMessage::LoggedIn(user) if user == name => return Ok(()),
+                ServerMessage::Other(s) if s.starts_with("Authentication failure") => {
+                    anyhow::bail!("Playtak login failed for {}", name)
+                }
+                _ => {}
+            }
+        }
+    }
+    pub async fn seek(&mut self, seek: &Seek) -> Result<()> {
+        self.send(&seek.to_server()).await
+    }
+    pub async fn send_move(&mut self, game: u32, mv: &PlaytakMove) -> Result<()> {
+        self.send(&format!("Game#{} {}", game, mv.to_server()))
+            .await
+    }
+}
+
+struct ActiveGame {
+    id: u32,
+    size: usize,
+    komi: u8,
+    color: Color,
+    moves: Vec<GameMove>,
+}
+
+impl ActiveGame {
+    fn new(start: &GameStart) -> Result<Self> {
+        if start.size != 5 && start.size != 6 {
+            anyhow::bail!("Unsupported board size {}", start.size);
+        }
+        Ok(Self {
+            id: start.id,
+            size: start.size,
+            komi: start.komi,
+            color: start.color,
+            moves: Vec::new(),
+        })
+    }
+    fn build_board(&self) -> TakGame {
+        let mut game = empty_game(self.size, self.komi).expect("Size is checked on creation");
+        for mv in self.moves.iter() {
+            game.do_move(*mv);
+        }
+        game
+    }
+    fn our_turn(&self) -> bool {
+        let board = self.build_board();
+        board.game_result().is_none() && board.side_to_move() == self.color
+    }
+    fn opponent_move(&mut self, mv: &PlaytakMove) -> Result<()> {
+        let ptn = mv
+            .to_ptn()
+            .ok_or_else(|| anyhow!("Bad server move {:?}", mv))?;
+        let board = self.build_board();
+        let mv = parse_move(&ptn, self.size, board.side_to_move())
+            .ok_or_else(|| anyhow!("Unable to parse server move {}", ptn))?;
+        // Server moves never say whether they crush a wall
+        let checked = match board {
+            TakGame::Standard5(board) => play::checked_move(&board, mv),
+            TakGame::Standard6(board) => play::checked_move(&board, mv),
+            _ => None,
+        };
+        let (mv, _) = checked.ok_or_else(|| anyhow!("Illegal server move {}", ptn))?;
+        self.moves.push(mv);
+        Ok(())
+    }
+    async fn respond(&mut self, client: &mut PlaytakClient, strength: Strength) -> Result<()> {
+        let board = self.build_board();
+        let (mv, ptn) =
+            tokio::task::spawn_blocking(move || play::choose_move(board, strength)).await??;
+        let server_move =
+            PlaytakMove::from_ptn(&ptn).ok_or_else(|| anyhow!("Unable to convert {}", ptn))?;
+        client.send_move(self.id, &server_move).await?;
+        self.moves.push(mv);
+        Ok(())
+    }
+}
+
+/// Seeks games and plays them with the engine, reseeking after each game ends.
+/// Stops after `max_games` games if a limit is given.
+pub async fn play_games(
+    client: &mut PlaytakClient,
+    seek: &Seek,
+    strength: Strength,
+    max_games: Option<usize>,
+) -> Result<()> {
+    client.seek(seek).await?;
+    let mut played = 0;
+    let mut game: Option<ActiveGame> = None;
+    // A game that was started but can't be played, resigned at once
+    let mut declined = None;
+    loop {
+        let message = match tokio::time::timeout(PING_INTERVAL, client.next_message()).await {
+            Ok(message) => message?,
+            Err(_) => {
+                client.send("PING").await?;
+                continue;
+            }
+        };
+        match message {
+            ServerMessage::GameStart(start) => match ActiveGame::new(&start) {
+                Ok(mut active) => {
+                    if active.our_turn() {
+                        active.respond(client, strength).await?;
+                    }
+                    game = Some(active);
+                }
+                Err(e) => {
+                    tracing::warn!("Declining playtak game {}: {}", start.id, e);
+                    client.send(&format!("Game#{} Resign", start.id)).await?;
+                    declined = Some(start.id);
+                }
+            },
+            ServerMessage::Move { game: id, mv } => {
+                if let Some(active) = game.as_mut().filter(|g| g.id == id) {
+                    active.opponent_move(&mv)?;
+                    if active.our_turn() {
+                        active.respond(client, strength).await?;
+                    }
+                }
+            }
+            ServerMessage::GameOver { game: id, result } => {
+                if game.as_ref().map(|g| g.id) == Some(id) {
+                    tracing::info!("Playtak game {} over: {}", id, result);
+                    game = None;
+                    played += 1;
+                    if Some(played) == max_games {
+                        return Ok(());
+                    }
+                    client.seek(seek).await?;
+                } else if declined == Some(id) {
+                    declined = None;
+                    client.seek(seek).await?;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Stands in for the playtak server by replaying a script. Lines starting
+    /// with `S:` are sent to the client, lines starting with `C:` must be
+    /// received from it. An expected line ending in `*` is matched as a prefix.
+    async fn scripted_server(
+        script: &'static str,
+    ) -> (std::net::SocketAddr, tokio::task::JoinHandle<Result<()>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            for line in script.lines().map(|x| x.trim()).filter(|x| !x.is_empty()) {
+                if let Some(out) = line.strip_prefix("S: ") {
+                    writer.write_all(format!("{}\n", out).as_bytes()).await?;
+                } else if let Some(expected) = line.strip_prefix("C: ") {
+                    let got = lines
+                        .next_line()
+                        .await?
+                        .ok_or_else(|| anyhow!("Client hung up"))?;
+                    let matched = match expected.strip_suffix('*') {
+                        Some(prefix) => got.starts_with(prefix),
+                        None => got == expected,
+                    };
+                    if !matched {
+                        anyhow::bail!("Expected {:?} but got {:?}", expected, got);
+                    }
+                }
+            }
+            Ok(())
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn move_notation() {
+        let cases = [
+            ("P A1", "a1"),
+            ("P C3 W", "Sc3"),
+            ("P D4 C", "Cd4"),
+            ("M A1 A3 2 1", "3a1+21"),
+            ("M B2 C2 1", "b2>"),
+            ("M C3 C2 2", "2c3-"),
+            ("M E5 B5 1 1 1", "3e5<111"),
+        ];
+        for (server, ptn) in cases {
+            let words: Vec<_> = server.split_whitespace().collect();
+            let mv = PlaytakMove::parse(&words).unwrap();
+            assert_eq!(mv.to_server(), server);
+            assert_eq!(mv.to_ptn().unwrap(), ptn);
+            assert_eq!(PlaytakMove::from_ptn(ptn).unwrap(), mv);
+        }
+        assert_eq!(
+            PlaytakMove::from_ptn("2c3-'").unwrap().to_server(),
+            "M C3 C2 2"
+        );
+    }
+
+    #[test]
+    fn server_messages() {
+        assert_eq!(
+            ServerMessage::parse("Game Start 7 5 alice vs topaz black 600 4 21 1"),
+            ServerMessage::GameStart(GameStart {
+                id: 7,
+                size: 5,
+                white: "alice".to_string(),
+                black: "topaz".to_string(),
+                color: Color::Black,
+                komi: 4,
+            })
+        );
+        assert_eq!(
+            ServerMessage::parse("Game#7 Over R-0"),
+            ServerMessage::GameOver {
+                game: 7,
+                result: "R-0".to_string()
+            }
+        );
+        assert_eq!(
+            ServerMessage::parse("Game#7 Time 590 600"),
+            ServerMessage::Other("Game#7 Time 590 600".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn login_and_seek() {
+        let (addr, server) = scripted_server(
+            "S: Welcome!
+             S: Login or Register
+             C: Login topaz secret
+             S: Welcome topaz!
+             C: Seek 6 600 10 A 4 30 1 0 0",
+        )
+        .await;
+        let mut client = PlaytakClient::connect(addr).await.unwrap();
+        client.login("topaz", "secret").await.unwrap();
+        let mut seek = Seek::new(6);
+        seek.komi = 4;
+        client.seek(&seek).await.unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn login_failure() {
+        let (addr, _server) = scripted_server(
+            "S: Login or Register
+             C: Login topaz wrong
+             S: Authentication failure",
+        )
+        .await;
+        let mut client = PlaytakClient::connect(addr).await.unwrap();
+        assert!(client.login("topaz", "wrong").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn plays_a_game() {
+        let (addr, server) = scripted_server(
+            "S: Login or Register
+             C: Login topaz secret
+             S: Welcome topaz!
+             C: Seek 5 600 10 A 0 21 1 0 0
+             S: Game Start 7 5 alice vs topaz black 600 0 21 1
+             S: Game#7 P A1
+             C: Game#7 P *
+             S: Game#7 Over 0-1",
+        )
+        .await;
+        let mut client = PlaytakClient::connect(addr).await.unwrap();
+        client.login("topaz", "secret").await.unwrap();
+        play_games(&mut client, &Seek::new(5), Strength::Beginner, Some(1))
+            .await
+            .unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn declines_unsupported_sizes() {
+        let (addr, server) = scripted_server(
+            "S: Login or Register
+             C: Login topaz secret
+             S: Welcome topaz!
+             C: Seek 5 600 10 A 0 21 1 0 0
+             S: Game Start 8 7 alice vs topaz black 600 0 40 2
+             C: Game#8 Resign
+             S: Game#8 Over 1-0
+             C: Seek 5 600 10 A 0 21 1 0 0
+             S: Game Start 9 5 alice vs topaz black 600 0 21 1
+             S: Game#9 P A1
+             C: Game#9 P *
+             S: Game#9 Over 0-1",
+        )
+        .await;
+        let mut client = PlaytakClient::connect(addr).await.unwrap();
+        client.login("topaz", "secret").await.unwrap();
+        play_games(&mut client, &Seek::new(5), Strength::Beginner, Some(1))
+            .await
+            .unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
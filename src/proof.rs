@@ -0,0 +1,270 @@
+use std::collections::HashSet;
+use std::fmt::Debug;
+
+use anyhow::{anyhow, Result};
+use inferno::flamegraph::{self, FuncFrameAttrsMap, Options};
+use serde::Serialize;
+use topaz_tak::{Color, GameMove, TakBoard};
+
+use super::graph::escape;
+
+const ATTACKER_FILL: &str = "#e8731a";
+const DEFENDER_FILL: &str = "#3b82c4";
+const IMAGE_WIDTH: usize = 1200;
+const EXPLORER: &str = include_str!("explorer.html");
+
+/// A tinue proof as rebuilt by the search: every line it checked, with the
+/// nodes spent on it
+#[derive(Debug, Clone)]
+pub struct Proof {
+    /// Position the proof starts from
+    pub tps: String,
+    pub attacker: Color,
+    pub move_num: usize,
+    pub tinue: bool,
+    pub lines: Vec<(Vec<String>, u64)>,
+}
+
+impl Proof {
+    /// Reads the folded stacks written by `TinueSearch::rebuild`, one
+    /// `move;move;move nodes` line per leaf
+    pub fn parse(tps: &str, attacker: Color, move_num: usize, tinue: bool, folded: &str) -> Self {
+        let lines = folded
+            .lines()
+            .filter_map(|line| {
+                let (stack, nodes) = line.trim().rsplit_once(' ')?;
+                let moves = stack.split(';').map(String::from).collect();
+                Some((moves, nodes.parse().ok()?))
+            })
+            .collect();
+        Self {
+            tps: tps.to_string(),
+            attacker,
+            move_num,
+            tinue,
+            lines,
+        }
+    }
+    fn color_at(&self, depth: usize) -> Color {
+        if depth % 2 == 0 {
+            self.attacker
+        } else {
+            !self.attacker
+        }
+    }
+    /// A move at some depth of the proof in ptn style, like `12. c3` or `12... d4`
+    pub fn label(&self, depth: usize, mv: &str) -> String {
+        let offset = match self.attacker {
+            Color::White => 0,
+            Color::Black => 1,
+        };
+        let move_num = self.move_num + (depth + offset) / 2;
+        match self.color_at(depth) {
+            Color::White => format!("{}. {}", move_num, mv),
+            Color::Black => format!("{}... {}", move_num, mv),
+        }
+    }
+    fn heading(&self) -> String {
+        let side = match self.attacker {
+            Color::White => "White",
+            Color::Black => "Black",
+        };
+        if self.tinue {
+            format!("Tinue for {} in {}", side, self.tps)
+        } else {
+            format!("No tinue found for {} in {}", side, self.tps)
+        }
+    }
+    /// Draws the proof as a flamegraph, attacker moves in orange and defences in blue,
+    /// with a box that searches the moves as you type
+    pub fn flamegraph(&self) -> Result<Vec<u8>> {
+        let mut fills = String::new();
+        let mut seen = HashSet::new();
+        let mut folded = Vec::new();
+        for (moves, nodes) in self.lines.iter() {
+            let labels: Vec<_> = moves
+                .iter()
+                .enumerate()
+                .map(|(depth, mv)| self.label(depth, mv))
+                .collect();
+            for (depth, label) in labels.iter().enumerate() {
+                if seen.insert(label.clone()) {
+                    let fill = if self.color_at(depth) == self.attacker {
+                        ATTACKER_FILL
+                    } else {
+                        DEFENDER_FILL
+                    };
+                    fills.push_str(&format!("{}\tfill={}\n", label, fill));
+                }
+            }
+            folded.push(format!("{} {}", labels.join(";"), nodes));
+        }
+        let mut options = Options::default();
+        options.title = self.heading();
+        options.subtitle = Some(String::from(
+            "Orange: attacker moves, blue: defences. Width is search nodes, \
+            hover for counts and click to zoom.",
+        ));
+        options.count_name = String::from("nodes");
+        options.name_type = String::from("Move:");
+        options.image_width = Some(IMAGE_WIDTH);
+        options.func_frameattrs = FuncFrameAttrsMap::from_reader(fills.as_bytes())?;
+        let mut svg = Vec::new();
+        flamegraph::from_lines(&mut options, folded.iter().map(|x| x.as_str()), &mut svg)?;
+        Ok(add_search_box(String::from_utf8(svg)?).into_bytes())
+    }
+    /// Writes the proof as a standalone html page with a collapsible move tree and
+    /// a board for the selected move. `board` is the position the proof starts from.
+    pub fn explorer<T: TakBoard + Debug + Clone>(&self, board: &T) -> Result<String> {
+        let mut root = ProofNode {
+            label: String::from("Start"),
+            ptn: String::new(),
+            attacker: false,
+            nodes: 0,
+            tps: format!("{:?}", board),
+            children: Vec::new(),
+        };
+        for (moves, nodes) in self.lines.iter() {
+            root.insert(self, board, 0, moves, *nodes)?;
+        }
+        root.sort();
+        // Keeps a move like `</script>` from ending the script early
+        let json = serde_json::to_string(&root)?.replace("</", "<\\/");
+        Ok(EXPLORER
+            .replace("{{title}}", &escape(&self.heading()))
+            .replace("{{proof}}", &json))
+    }
+}
+
+/// A move of the proof with every reply the search checked after it
+#[derive(Debug, Serialize)]
+struct ProofNode {
+    label: String,
+    #[serde(skip)]
+    ptn: String,
+    attacker: bool,
+    /// Search nodes spent under this move
+    nodes: u64,
+    /// Position after the move
+    tps: String,
+    children: Vec<ProofNode>,
+}
+
+impl ProofNode {
+    fn insert<T: TakBoard + Debug + Clone>(
+        &mut self,
+        proof: &Proof,
+        board: &T,
+        depth: usize,
+        moves: &[String],
+        nodes: u64,
+    ) -> Result<()> {
+        self.nodes += nodes;
+        let (mv, rest) = match moves.split_first() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        let mut next = board.clone();
+        let game_move = GameMove::try_from_ptn(mv, &next)
+            .ok_or_else(|| anyhow!("Unable to read proof move {}", mv))?;
+        next.do_move(game_move);
+        let idx = match self.children.iter().position(|x| x.ptn == *mv) {
+            Some(idx) => idx,
+            None => {
+                self.children.push(ProofNode {
+                    label: proof.label(depth, mv),
+                    ptn: mv.clone(),
+                    attacker: depth % 2 == 0,
+                    nodes: 0,
+                    tps: format!("{:?}", next),
+                    children: Vec::new(),
+                });
+                self.children.len() - 1
+            }
+        };
+        self.children[idx].insert(proof, &next, depth + 1, rest, nodes)
+    }
+    /// Puts the most searched replies first
+    fn sort(&mut self) {
+        self.children.sort_by(|a, b| b.nodes.cmp(&a.nodes));
+        for child in self.children.iter_mut() {
+            child.sort();
+        }
+    }
+}
+
+/// Runs the flamegraph's own search, treating the text literally rather than as a regex
+const SEARCH_SCRIPT: &str = r#"<script type="text/ecmascript"><![CDATA[
+function topaz_search(text) {
+    if (text) {
+        search(text.replace(/[.*+?^${}()|[\]\\]/g, "\\$&"));
+    } else {
+        reset_search();
+    }
+}
+]]></script>
+"#;
+
+/// Adds a text box that searches the moves on every key press
+fn add_search_box(svg: String) -> String {
+    let search_box = format!(
+        r#"<foreignObject x="{}" y="6" width="200" height="24"><input xmlns="http://www.w3.org/1999/xhtml" type="search" placeholder="Search moves, like c3+" style="width: 190px" oninput="topaz_search(this.value)"/></foreignObject>
+"#,
+        IMAGE_WIDTH - 310
+    );
+    match svg.rfind("</svg>") {
+        Some(end) => format!(
+            "{}{}{}{}",
+            &svg[..end],
+            search_box,
+            SEARCH_SCRIPT,
+            &svg[end..]
+        ),
+        None => svg,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use topaz_tak::board::Board5;
+
+    fn proof() -> Proof {
+        let folded = "c3;d4;c4 12\nc3;d3;c4 30\nbad line\nc3;d2 5\n";
+        Proof::parse("x5/x5/x5/x5/x5 2 3", Color::Black, 3, true, folded)
+    }
+
+    #[test]
+    fn parses_and_labels() {
+        let proof = proof();
+        assert_eq!(proof.lines.len(), 3);
+        assert_eq!(
+            proof.lines[1],
+            (vec!["c3".into(), "d3".into(), "c4".into()], 30)
+        );
+        assert_eq!(proof.label(0, "c3"), "3... c3");
+        assert_eq!(proof.label(1, "d4"), "4. d4");
+        assert_eq!(proof.label(2, "c4"), "4... c4");
+    }
+
+    #[test]
+    fn draws_flamegraph() {
+        let svg = String::from_utf8(proof().flamegraph().unwrap()).unwrap();
+        assert!(svg.contains("Tinue for Black in x5/x5/x5/x5/x5 2 3"));
+        assert!(svg.contains(ATTACKER_FILL) && svg.contains(DEFENDER_FILL));
+        assert!(svg.contains("nodes"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains("topaz_search"));
+    }
+
+    #[test]
+    fn builds_explorer() {
+        let board = Board5::try_from_tps("x5/x5/x5/x5/x5 2 3").unwrap();
+        let html = proof().explorer(&board).unwrap();
+        assert!(html.contains("<title>Tinue for Black in x5/x5/x5/x5/x5 2 3</title>"));
+        assert!(!html.contains("{{"));
+        // The two lines through d3 and d4 share their first move, c3
+        assert!(html.contains(r#"{"label":"3... c3","attacker":true,"nodes":47,"#));
+        assert!(html.contains(r#""label":"4. d3","attacker":false,"nodes":30"#));
+    }
+}
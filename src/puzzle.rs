@@ -1,56 +1,71 @@
 use super::Board6;
+use anyhow::{anyhow, Result};
 use lazy_static::lazy_static;
 use rand::seq::SliceRandom;
 use std::fs::read_to_string;
 use topaz_tak::{generate_all_moves, search::proof::TinueSearch, GameMove, Position, TakBoard};
 
-pub fn new_puzzle(id: usize) -> Option<PuzzleState> {
-    // Todo make sure id is valid
-    Some(PuzzleState::new(id, PUZZLES.get(id)?))
+pub fn new_puzzle(id: usize) -> Result<Option<PuzzleState>> {
+    Ok(puzzles()?.get(id).map(|data| PuzzleState::new(id, data)))
 }
 
-pub fn random_puzzle(difficulty: Difficulty) -> PuzzleState {
-    DIFFICULTIES.choose(difficulty)
+pub fn random_puzzle(difficulty: Difficulty) -> Result<PuzzleState> {
+    puzzles()?;
+    Ok(DIFFICULTIES.choose(difficulty))
 }
 
-pub fn puzzle_length() -> usize {
-    PUZZLES.len()
+pub fn puzzle_length() -> Result<usize> {
+    Ok(puzzles()?.len())
 }
 
 lazy_static! {
     static ref DIFFICULTIES: Difficulties = Difficulties::new();
-    static ref PUZZLES: Vec<PuzzleData> = {
-        let data = read_to_string("tinue_data.csv").unwrap();
-        data.lines()
-            .skip(1)
-            .enumerate()
-            .map(|(puzzle_id, line)| {
-                let split: Vec<_> = line.split(";").collect();
-                let id = split[0];
-                let tps = split[1].to_string();
-                let pv = split[2];
-                let pv = pv.split(" ").map(|x| x.to_string()).collect::<Vec<_>>();
-                let nodes = split[3].parse::<f32>().unwrap();
-                let root_nodes = split[4].parse::<f32>().unwrap();
-                let mut difficulty = 0.0;
-                if root_nodes >= 4.0 {
-                    difficulty += 0.5;
-                }
-                difficulty += (nodes / 50_000.0).clamp(0.0, 1.5);
-                difficulty += (pv.len() as f32 / 5.0).clamp(0.0, 2.0);
-                if pv.len() <= 3 {
-                    difficulty = difficulty.min(0.5)
-                }
-                PuzzleData {
-                    puzzle_id,
-                    game_id: id.parse().unwrap(),
-                    tps,
-                    pv,
-                    difficulty,
-                }
-            })
-            .collect()
-    };
+    static ref PUZZLES: Result<Vec<PuzzleData>, String> =
+        load_puzzles(&super::config().paths.puzzles).map_err(|e| e.to_string());
+}
+
+/// The puzzles from the csv, or why they could not be loaded
+fn puzzles() -> Result<&'static [PuzzleData]> {
+    PUZZLES.as_deref().map_err(|e| anyhow!("{}", e))
+}
+
+fn load_puzzles(path: &str) -> Result<Vec<PuzzleData>> {
+    let data =
+        read_to_string(path).map_err(|e| anyhow!("Unable to read puzzles from {}: {}", path, e))?;
+    data.lines()
+        .skip(1)
+        .enumerate()
+        .map(|(puzzle_id, line)| {
+            parse_puzzle(puzzle_id, line)
+                .ok_or_else(|| anyhow!("Unable to read puzzle {} in {}", puzzle_id, path))
+        })
+        .collect()
+}
+
+fn parse_puzzle(puzzle_id: usize, line: &str) -> Option<PuzzleData> {
+    let split: Vec<_> = line.split(";").collect();
+    let id = split.get(0)?;
+    let tps = split.get(1)?.to_string();
+    let pv = split.get(2)?;
+    let pv = pv.split(" ").map(|x| x.to_string()).collect::<Vec<_>>();
+    let nodes = split.get(3)?.parse::<f32>().ok()?;
+    let root_nodes = split.get(4)?.parse::<f32>().ok()?;
+    let mut difficulty = 0.0;
+    if root_nodes >= 4.0 {
+        difficulty += 0.5;
+    }
+    difficulty += (nodes / 50_000.0).clamp(0.0, 1.5);
+    difficulty += (pv.len() as f32 / 5.0).clamp(0.0, 2.0);
+    if pv.len() <= 3 {
+        difficulty = difficulty.min(0.5)
+    }
+    Some(PuzzleData {
+        puzzle_id,
+        game_id: id.parse().ok()?,
+        tps,
+        pv,
+        difficulty,
+    })
 }
 
 struct Difficulties {
@@ -60,7 +75,7 @@ struct Difficulties {
 impl Difficulties {
     fn new() -> Self {
         let mut data = [Vec::new(), Vec::new(), Vec::new(), Vec::new()];
-        for (pid, p) in PUZZLES.iter().enumerate() {
+        for (pid, p) in puzzles().unwrap_or(&[]).iter().enumerate() {
             let idx = p.human_difficulty() as usize;
             data[idx].push(pid as u16);
         }
@@ -70,7 +85,8 @@ impl Difficulties {
         let mut rng = rand::thread_rng();
         let slice = &self.data[diff as usize];
         let idx = slice.choose(&mut rng).unwrap_or(&0);
-        new_puzzle(*idx as usize).unwrap()
+        let data = &puzzles().expect("Puzzles are loaded before choosing one")[*idx as usize];
+        PuzzleState::new(*idx as usize, data)
     }
 }
 
@@ -121,14 +137,17 @@ impl PuzzleState {
             is_tinue: true,
         }
     }
+    fn data(&self) -> &'static PuzzleData {
+        &puzzles().expect("A puzzle only exists once they are loaded")[self.puzzle_num]
+    }
     pub fn id(&self) -> usize {
-        PUZZLES[self.puzzle_num].puzzle_id
+        self.data().puzzle_id
     }
     pub fn initial_pv(&self) -> &Vec<String> {
-        &PUZZLES[self.puzzle_num].pv
+        &self.data().pv
     }
     pub fn initial_tps(&self) -> String {
-        PUZZLES[self.puzzle_num].tps.clone()
+        self.data().tps.clone()
     }
     pub fn initial_side(&self) -> topaz_tak::Color {
         let board = Board6::try_from_tps(&self.initial_tps()).unwrap();
@@ -136,7 +155,7 @@ impl PuzzleState {
     }
     pub fn build_board(&self) -> Board6 {
         // Todo eventually make this work with other sizes
-        let mut board = Board6::try_from_tps(&PUZZLES[self.puzzle_num].tps).unwrap();
+        let mut board = Board6::try_from_tps(&self.data().tps).unwrap();
         for mv in self.active_moves.iter() {
             let mv = GameMove::try_from_ptn(mv, &board).unwrap();
             board.do_move(mv);
@@ -229,7 +248,7 @@ impl PuzzleState {
         }
     }
     pub fn human_difficulty(&self) -> Difficulty {
-        PUZZLES[self.puzzle_num].human_difficulty()
+        self.data().human_difficulty()
     }
 }
 
@@ -280,10 +299,25 @@ impl PuzzleData {
 // Guess cutoffs: <1, 2.20, 3.5, 4.0
 
 pub fn list_difficulties() {
-    for puzzle in PUZZLES.iter() {
+    for puzzle in puzzles().unwrap_or(&[]).iter() {
         if puzzle.difficulty >= 3.0 {
             println!("{}: {}, {:?}", puzzle.difficulty, puzzle.tps, puzzle.pv);
         }
         // println!("{}", puzzle.difficulty);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn loads_puzzles() {
+        let puzzles = load_puzzles("tests/data/puzzles.csv").unwrap();
+        assert_eq!(puzzles.len(), 1);
+        assert_eq!(puzzles[0].game_id, 123);
+        let err = load_puzzles("missing.csv").unwrap_err().to_string();
+        assert!(err.starts_with("Unable to read puzzles from missing.csv"));
+        assert!(parse_puzzle(0, "123;x6/x6/x6/x6/x6/x6 1 1").is_none());
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use super::*;
+
+/// Channel of the terminal itself, game threads are numbered after it
+const MAIN_CHANNEL: u64 = 1;
+const USER: u64 = 1;
+
+/// Prints what the bot would send and saves attachments to a directory
+pub struct ReplFrontend {
+    out_dir: PathBuf,
+    channel: AtomicU64,
+    next_thread: AtomicU64,
+}
+
+impl ReplFrontend {
+    pub fn new(out_dir: PathBuf) -> Self {
+        Self {
+            out_dir,
+            channel: AtomicU64::new(MAIN_CHANNEL),
+            next_thread: AtomicU64::new(MAIN_CHANNEL + 1),
+        }
+    }
+    /// The channel typed lines go to, a game thread while a game is running
+    fn channel(&self) -> u64 {
+        let channel = self.channel.load(Ordering::Relaxed);
+        if channel != MAIN_CHANNEL && !play::is_game_channel(channel) {
+            println!("(back in the main channel)");
+            self.channel.store(MAIN_CHANNEL, Ordering::Relaxed);
+            return MAIN_CHANNEL;
+        }
+        channel
+    }
+    fn request(&self, user_name: &str, content: &str) -> Request {
+        Request {
+            user: USER,
+            user_name: user_name.to_string(),
+            channel: self.channel(),
+            guild: None,
+            message: None,
+            content: content.to_string(),
+            attachments: Vec::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl ChatFrontend for ReplFrontend {
+    async fn reply(&self, text: &str) -> Result<()> {
+        println!("{}", text);
+        Ok(())
+    }
+    async fn send(&self, channel: u64, text: &str) -> Result<()> {
+        if channel == MAIN_CHANNEL {
+            println!("{}", text);
+        } else {
+            println!("[thread {}] {}", channel, text);
+        }
+        Ok(())
+    }
+    async fn send_files(&self, channel: u64, text: &str, files: Vec<Attachment>) -> Result<()> {
+        self.send(channel, text).await?;
+        tokio::fs::create_dir_all(&self.out_dir).await?;
+        for file in files {
+            let path = self.out_dir.join(&file.name);
+            tokio::fs::write(&path, &file.data).await?;
+            println!("(saved {})", path.display());
+        }
+        Ok(())
+    }
+    async fn react(&self, emoji: &str) -> Result<()> {
+        println!("({})", emoji);
+        Ok(())
+    }
+    async fn create_thread(&self, name: &str) -> Result<u64> {
+        let thread = self.next_thread.fetch_add(1, Ordering::Relaxed);
+        self.channel.store(thread, Ordering::Relaxed);
+        println!(
+            "(opened thread {} \"{}\", your messages now go there)",
+            thread, name
+        );
+        Ok(thread)
+    }
+    /// Nobody else is watching, so bare moves always answer the puzzle
+    fn is_puzzle_channel(&self, _request: &Request) -> bool {
+        true
+    }
+}
+
+/// Reads commands from stdin until it closes, printing the replies the bot would send
+pub async fn run(out_dir: PathBuf) -> Result<()> {
+    let frontend = ReplFrontend::new(out_dir);
+    let user_name = std::env::var("USER").unwrap_or_else(|_| String::from("repl"));
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        handle_request(&frontend, &frontend.request(&user_name, line)).await;
+    }
+    Ok(())
+}
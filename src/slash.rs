@@ -0,0 +1,298 @@
+use serenity::builder::{CreateApplicationCommand, CreateApplicationCommandOption};
+use serenity::model::application::command::{Command, CommandOptionType};
+use serenity::model::application::interaction::application_command::{
+    ApplicationCommandInteraction, CommandDataOption, CommandDataOptionValue,
+};
+use serenity::model::application::interaction::autocomplete::AutocompleteInteraction;
+use serenity::model::application::interaction::{Interaction, InteractionResponseType};
+
+use super::*;
+
+/// Discord shows at most this many autocomplete suggestions
+const MAX_CHOICES: usize = 25;
+const SOLVE_ACTIONS: [&str; 5] = ["legal", "pv", "undo", "tps", "link"];
+
+/// Registers the slash commands, which sit alongside the `!` commands
+pub async fn register(context: &Context) -> Result<()> {
+    Command::set_global_application_commands(&context.http, |commands| {
+        commands
+            .create_application_command(create_tinue)
+            .create_application_command(create_puzzle)
+            .create_application_command(create_solve)
+    })
+    .await?;
+    Ok(())
+}
+
+fn describe<'a>(
+    command: &'a mut CreateApplicationCommand,
+    kind: commands::CommandKind,
+) -> &'a mut CreateApplicationCommand {
+    let info = commands::COMMANDS
+        .iter()
+        .find(|c| c.kind == kind)
+        .expect("Slash command is in the registry");
+    command.name(info.name).description(info.description)
+}
+
+fn create_tinue(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    describe(command, commands::CommandKind::Tinue)
+        .create_option(|o| {
+            o.name("game")
+                .description("Playtak id, ptn.ninja link, tps or ptn")
+                .kind(CommandOptionType::String)
+                .required(true)
+        })
+        .create_option(|o| {
+            o.name("size")
+                .description("Board size, for bare moves without a Size tag")
+                .kind(CommandOptionType::Integer)
+                .add_int_choice("5x5", 5)
+                .add_int_choice("6x6", 6)
+                .add_int_choice("7x7", 7)
+        })
+}
+
+fn create_puzzle(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    describe(command, commands::CommandKind::Puzzle)
+        .create_option(|o| {
+            o.name("difficulty")
+                .description("Pick a random puzzle of this difficulty")
+                .kind(CommandOptionType::String)
+                .add_string_choice("Easy", "easy")
+                .add_string_choice("Medium", "medium")
+                .add_string_choice("Hard", "hard")
+                .add_string_choice("Insane", "insane")
+        })
+        .create_option(|o| {
+            o.name("id")
+                .description("Play a specific puzzle instead")
+                .kind(CommandOptionType::Integer)
+        })
+}
+
+fn create_solve(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    describe(command, commands::CommandKind::Solve)
+        .create_option(|o| {
+            o.name("move")
+                .description("Your next move in ptn")
+                .kind(CommandOptionType::String)
+                .set_autocomplete(true)
+        })
+        .create_option(solve_action)
+}
+
+fn solve_action(
+    option: &mut CreateApplicationCommandOption,
+) -> &mut CreateApplicationCommandOption {
+    option
+        .name("action")
+        .description("Get help with the puzzle")
+        .kind(CommandOptionType::String);
+    for name in SOLVE_ACTIONS {
+        option.add_string_choice(name, name);
+    }
+    option
+}
+
+pub async fn handle_interaction(context: &Context, interaction: Interaction) -> Result<()> {
+    match interaction {
+        Interaction::ApplicationCommand(command) => {
+            if let Some(info) = commands::find(&command.data.name) {
+                metrics::record_request(info.name);
+            }
+            match command.data.name.as_str() {
+                "tinue" => run_tinue(context, &command).await,
+                "puzzle" => run_puzzle(context, &command).await,
+                "solve" => run_solve(context, &command).await,
+                _ => Ok(()),
+            }
+        }
+        Interaction::Autocomplete(autocomplete) => suggest_moves(context, &autocomplete).await,
+        _ => Ok(()),
+    }
+}
+
+fn string_option<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a str> {
+    options
+        .iter()
+        .find(|o| o.name == name)
+        .and_then(|o| o.value.as_ref())
+        .and_then(|v| v.as_str())
+}
+
+fn int_option(options: &[CommandDataOption], name: &str) -> Option<i64> {
+    match options.iter().find(|o| o.name == name)?.resolved {
+        Some(CommandDataOptionValue::Integer(x)) => Some(x),
+        _ => None,
+    }
+}
+
+async fn respond(
+    context: &Context,
+    command: &ApplicationCommandInteraction,
+    content: String,
+    ephemeral: bool,
+) -> Result<()> {
+    command
+        .create_interaction_response(&context.http, |r| {
+            r.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|d| d.content(content).ephemeral(ephemeral))
+        })
+        .await?;
+    Ok(())
+}
+
+/// Puzzles only live in the puzzle channel, so say where to go instead
+async fn check_puzzle_channel(
+    context: &Context,
+    command: &ApplicationCommandInteraction,
+) -> Result<bool> {
+    match config().puzzle_channel(command.guild_id.map(|g| g.0)) {
+        Some(channel) if channel == command.channel_id.0 => Ok(true),
+        Some(channel) => {
+            let content = format!("Puzzles are played in {}", ChannelId(channel).mention());
+            respond(context, command, content, true).await?;
+            Ok(false)
+        }
+        None => {
+            let content = String::from("Puzzles are not enabled on this server.");
+            respond(context, command, content, true).await?;
+            Ok(false)
+        }
+    }
+}
+
+/// Prepends a Size tag to bare moves, leaving every other kind of input alone.
+/// The input is read the same way as for the `!` commands, so a tps with a komi
+/// after it is not mistaken for moves.
+fn with_size_tag(game: &str, size: Option<i64>) -> String {
+    let bare_moves = match input::GameInput::detect(game, &[]) {
+        Ok(detected) => {
+            matches!(detected.source, input::Source::Ptn(ref ptn) if !ptn.contains('['))
+        }
+        Err(_) => false,
+    };
+    match size {
+        Some(size) if bare_moves => format!("[Size \"{}\"]\n\n{}", size, game),
+        _ => game.to_string(),
+    }
+}
+
+async fn run_tinue(context: &Context, command: &ApplicationCommandInteraction) -> Result<()> {
+    let options = &command.data.options;
+    let game = string_option(options, "game").unwrap_or("");
+    let details = with_size_tag(game, int_option(options, "size"));
+    // Searches take longer than the three seconds Discord waits for a response
+    command
+        .create_interaction_response(&context.http, |r| {
+            r.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+        })
+        .await?;
+    let owner = jobs::Owner {
+        user: command.user.id.0,
+        message: None,
+    };
+    let report = match tinue_report(&details, &[], &command.user.name, owner).await {
+        Ok(report) => report,
+        Err(e) => {
+            let content = if let Some(stopped) = e.downcast_ref::<jobs::Stopped>() {
+                stopped.to_string()
+            } else {
+                tracing::warn!("Error handling tinue command: {}", e);
+                String::from("Sorry, I could not read that game.")
+            };
+            TinueReport {
+                content,
+                files: Vec::new(),
+            }
+        }
+    };
+    command
+        .create_followup_message(&context.http, |f| {
+            f.content(&report.content);
+            for file in report.files.iter() {
+                f.add_file((file.data.as_slice(), file.name.as_str()));
+            }
+            f
+        })
+        .await?;
+    Ok(())
+}
+
+async fn run_puzzle(context: &Context, command: &ApplicationCommandInteraction) -> Result<()> {
+    if !check_puzzle_channel(context, command).await? {
+        return Ok(());
+    }
+    let options = &command.data.options;
+    let query = match (
+        int_option(options, "id"),
+        string_option(options, "difficulty"),
+    ) {
+        (Some(id), _) => id.to_string(),
+        (None, Some(difficulty)) => difficulty.to_string(),
+        (None, None) => String::from("easy"),
+    };
+    let reply = start_puzzle(command.user.id.0, &query)
+        .unwrap_or_else(|| String::from("Sorry, I could not load that puzzle."));
+    respond(context, command, reply, true).await
+}
+
+async fn run_solve(context: &Context, command: &ApplicationCommandInteraction) -> Result<()> {
+    if !check_puzzle_channel(context, command).await? {
+        return Ok(());
+    }
+    let options = &command.data.options;
+    let user = command.user.id.0;
+    let reply = if let Some(mv) = string_option(options, "move") {
+        puzzle_move(user, mv).unwrap_or_else(|| format!("{} is not a ptn move", mv))
+    } else if let Some(action) = string_option(options, "action") {
+        solve_command(user, action)
+    } else {
+        String::from("Give either a move or an action.")
+    };
+    respond(context, command, reply, true).await
+}
+
+/// Offers the legal moves of the user's puzzle that start with what they typed
+async fn suggest_moves(context: &Context, autocomplete: &AutocompleteInteraction) -> Result<()> {
+    let typed = autocomplete
+        .data
+        .options
+        .iter()
+        .find(|o| o.focused)
+        .and_then(|o| o.value.as_ref())
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let moves = puzzle_legal_moves(autocomplete.user.id.0);
+    autocomplete
+        .create_autocomplete_response(&context.http, |r| {
+            for mv in moves
+                .iter()
+                .filter(|mv| mv.to_ascii_lowercase().starts_with(&typed))
+                .take(MAX_CHOICES)
+            {
+                r.add_string_choice(mv, mv);
+            }
+            r
+        })
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn size_tag() {
+        assert_eq!(with_size_tag("a1 f6", Some(6)), "[Size \"6\"]\n\na1 f6");
+        assert_eq!(with_size_tag("a1 f6", None), "a1 f6");
+        assert_eq!(with_size_tag("123456", Some(6)), "123456");
+        let ptn = "[Size \"5\"]\n\n1. a1 e5";
+        assert_eq!(with_size_tag(ptn, Some(6)), ptn);
+        let tps = "x5/x5/x5/x5/1,x4 2 1 komi 2.5";
+        assert_eq!(with_size_tag(tps, Some(5)), tps);
+    }
+}
@@ -0,0 +1,195 @@
+//! Replays the transcripts in `tests/transcripts` through the command handler.
+//!
+//! A transcript is a list of messages, each followed by what the bot sends back:
+//!
+//! ```text
+//! # Comment
+//! @20              messages now come from channel 20
+//! > !solve pv      a message from the user
+//! reply: c3        what the bot should answer
+//! ```
+//!
+//! Bot output is one of `reply: text`, `send #channel: text`, `attachment name`,
+//! `react emoji` or `thread #channel name`, with multi-line text continuing on the
+//! following lines. An expected line ending in `...` matches any line starting with
+//! the rest of it, for output like links that is awkward to write out.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, Once};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use topaz_discord::config::Config;
+use topaz_discord::frontend::{Attachment, ChatFrontend, Request};
+
+const GUILD: u64 = 10;
+const USER: u64 = 5;
+
+/// Stands in for Discord, recording everything the bot sends
+#[derive(Default)]
+struct FakeDiscord {
+    sent: Mutex<Vec<String>>,
+    next_thread: AtomicU64,
+}
+
+impl FakeDiscord {
+    fn record(&self, line: String) {
+        self.sent.lock().unwrap().push(line);
+    }
+    /// Everything sent since the last call, one line per entry
+    fn take(&self) -> Vec<String> {
+        let sent = std::mem::take(&mut *self.sent.lock().unwrap());
+        sent.iter()
+            .flat_map(|x| x.lines())
+            .filter(|x| !x.trim().is_empty())
+            .map(String::from)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl ChatFrontend for FakeDiscord {
+    async fn reply(&self, text: &str) -> Result<()> {
+        self.record(format!("reply: {}", text));
+        Ok(())
+    }
+    async fn send(&self, channel: u64, text: &str) -> Result<()> {
+        self.record(format!("send #{}: {}", channel, text));
+        Ok(())
+    }
+    async fn send_files(&self, channel: u64, text: &str, files: Vec<Attachment>) -> Result<()> {
+        self.record(format!("send #{}: {}", channel, text));
+        for file in files {
+            self.record(format!("attachment {}", file.name));
+        }
+        Ok(())
+    }
+    async fn react(&self, emoji: &str) -> Result<()> {
+        self.record(format!("react {}", emoji));
+        Ok(())
+    }
+    async fn create_thread(&self, name: &str) -> Result<u64> {
+        let thread = 100 + self.next_thread.fetch_add(1, Ordering::Relaxed);
+        self.record(format!("thread #{} {}", thread, name));
+        Ok(thread)
+    }
+}
+
+fn setup() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let config = Config::parse(
+            "[paths]\npuzzles = \"tests/data/puzzles.csv\"\n\n\
+            [[guild]]\nid = 10\npuzzle_channel = 20\n",
+        )
+        .unwrap();
+        topaz_discord::init(config, &[]).unwrap();
+    });
+}
+
+fn matches(expected: &str, actual: &str) -> bool {
+    match expected.strip_suffix("...") {
+        Some(prefix) => actual.starts_with(prefix),
+        None => expected == actual,
+    }
+}
+
+struct Step {
+    line: usize,
+    channel: u64,
+    content: String,
+    expected: Vec<String>,
+}
+
+fn parse_transcript(text: &str) -> Vec<Step> {
+    let mut steps: Vec<Step> = Vec::new();
+    let mut channel = 1;
+    for (idx, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(id) = line.strip_prefix('@') {
+            channel = id.trim().parse().expect("Channel is a number");
+        } else if let Some(content) = line.strip_prefix("> ") {
+            steps.push(Step {
+                line: idx + 1,
+                channel,
+                content: content.to_string(),
+                expected: Vec::new(),
+            });
+        } else {
+            let step = steps.last_mut().expect("Output follows a message");
+            step.expected.push(line.to_string());
+        }
+    }
+    steps
+}
+
+async fn replay(path: &Path) -> Vec<String> {
+    let text = std::fs::read_to_string(path).unwrap();
+    let frontend = FakeDiscord::default();
+    let mut failures = Vec::new();
+    for step in parse_transcript(&text) {
+        let request = Request {
+            user: USER,
+            user_name: String::from("tester"),
+            channel: step.channel,
+            guild: Some(GUILD),
+            message: Some(step.line as u64),
+            content: step.content.clone(),
+            attachments: Vec::new(),
+        };
+        topaz_discord::handle_request(&frontend, &request).await;
+        let actual = frontend.take();
+        let same = actual.len() == step.expected.len()
+            && step
+                .expected
+                .iter()
+                .zip(actual.iter())
+                .all(|(e, a)| matches(e, a));
+        if !same {
+            failures.push(format!(
+                "{}:{} > {}\nexpected:\n{}\nactual:\n{}",
+                path.display(),
+                step.line,
+                step.content,
+                step.expected.join("\n"),
+                actual.join("\n")
+            ));
+        }
+    }
+    failures
+}
+
+#[tokio::test]
+async fn transcripts() {
+    setup();
+    let mut paths: Vec<_> = std::fs::read_dir("tests/transcripts")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |x| x == "txt"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    let mut failures = Vec::new();
+    // Puzzles and games are global, so transcripts run one after another
+    for path in paths {
+        failures.extend(replay(&path).await);
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
+
+#[test]
+fn wildcards() {
+    assert!(matches(
+        "reply: https://ptn.ninja/...",
+        "reply: https://ptn.ninja/NoBQ"
+    ));
+    assert!(!matches("reply: f1", "reply: f1 f2"));
+    let steps = parse_transcript("# c\n@20\n> !ping\nsend #20: Pong!\n\n> hi\n");
+    assert_eq!(steps.len(), 2);
+    assert_eq!(steps[0].channel, 20);
+    assert_eq!(steps[0].expected, vec!["send #20: Pong!"]);
+    assert!(steps[1].expected.is_empty());
+}